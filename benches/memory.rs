@@ -0,0 +1,63 @@
+//! Heap used per `Cc`, against the header layout that kept an
+//! `Arc<CycleCollector>` in every box.
+//!
+//! Run with `cargo bench --bench memory`.
+#![allow(clippy::arc_with_non_send_sync)]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use cc_bacon::{Cc, CcBoxMetaData, CollectionPolicy, Color, CycleCollector};
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// The metadata as it was with a reference to the collector in each box.
+#[allow(dead_code)]
+struct ArcMetaData {
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    buffered: Cell<bool>,
+    finalize: Cell<bool>,
+    color: Cell<Color>,
+    root: Arc<CycleCollector>,
+}
+
+const COUNT: usize = 100_000;
+
+fn main() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let mut ccs = Vec::with_capacity(COUNT);
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    for i in 0..COUNT {
+        ccs.push(Cc::new(i as u64, &root));
+    }
+    let per_object = (ALLOCATED.load(Ordering::Relaxed) - before) / COUNT;
+    drop(ccs);
+
+    let old = size_of::<ArcMetaData>();
+    let new = size_of::<CcBoxMetaData>();
+    println!("header: {old} bytes before, {new} bytes now");
+    println!(
+        "Cc<u64>: {} bytes before, {per_object} bytes now",
+        size_of::<(ArcMetaData, u64)>()
+    );
+}
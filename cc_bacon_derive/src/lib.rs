@@ -0,0 +1,555 @@
+//! `#[derive(Trace)]` for `cc_bacon`, re-exported from there behind the
+//! `derive` feature.
+//!
+//! Every field is traced with its own `Trace` impl unless it says otherwise:
+//! - `#[trace(skip)]` leaves the field out, for fields that can't own a `Cc`
+//!   or don't implement `Trace`;
+//! - `#[trace(unsafe(with = path))]` traces it with `path(&field, tracer)`
+//!   instead, which has to keep the contract of the unsafe `Trace` trait.
+//!   `#[trace(with = path)]`, from before `Trace` was unsafe, is an error.
+//!
+//! `#[trace(acyclic)]` on the type itself makes `Trace::is_acyclic` return
+//! true, promising that its values never own a `Cc`.
+//!
+//! Every type parameter gets a `Trace` bound.
+//!
+//! The generated `unsafe impl` reports what the fields report, so it keeps
+//! `Trace`'s contract as long as their impls do.
+//!
+//! There is no `syn` here, so the item is parsed by hand from the token
+//! stream; it only needs to understand enough of it to find the fields.
+use proc_macro::{Delimiter, Group, Spacing, Span, TokenStream, TokenTree};
+
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    match Item::parse(input) {
+        Ok(item) => item.expand().parse().expect("generated invalid tokens"),
+        Err(err) => {
+            let code: TokenStream = format!("::core::compile_error!({:?});", err.msg)
+                .parse()
+                .unwrap();
+            respan(code, err.span)
+        }
+    }
+}
+
+/// Move every token of `tokens` to `span`, so that errors point there.
+fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut tt| {
+            if let TokenTree::Group(g) = &tt {
+                let mut group = Group::new(g.delimiter(), respan(g.stream(), span));
+                group.set_span(span);
+                tt = TokenTree::Group(group);
+            }
+            tt.set_span(span);
+            tt
+        })
+        .collect()
+}
+
+/// Why the item couldn't be parsed, and where.
+struct Error {
+    msg: String,
+    span: Span,
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Error {
+        Error {
+            msg,
+            span: Span::call_site(),
+        }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Error {
+        msg.to_string().into()
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How a field is traced, as set by its `#[trace(..)]` attribute.
+enum Mode {
+    Trace,
+    Skip,
+    With(String),
+}
+
+struct Field {
+    /// `None` for tuple fields.
+    name: Option<String>,
+    mode: Mode,
+}
+
+enum Fields {
+    Named(Vec<Field>),
+    Unnamed(Vec<Field>),
+    Unit,
+}
+
+struct Variant {
+    name: String,
+    fields: Fields,
+}
+
+enum Data {
+    Struct(Fields),
+    Enum(Vec<Variant>),
+}
+
+struct Param {
+    /// The declaration without its default, for the `impl<..>`.
+    decl: String,
+    /// What to pass for it in `Name<..>`.
+    arg: String,
+    is_type: bool,
+}
+
+struct Item {
+    name: String,
+    acyclic: bool,
+    params: Vec<Param>,
+    where_clause: String,
+    data: Data,
+}
+
+fn tokens_to_string(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn is_punct(tt: &TokenTree, c: char) -> bool {
+    matches!(tt, TokenTree::Punct(p) if p.as_char() == c)
+}
+
+fn is_ident(tt: &TokenTree, s: &str) -> bool {
+    matches!(tt, TokenTree::Ident(i) if i.to_string() == s)
+}
+
+/// Split on the commas that aren't nested in a group or in `<..>`.
+fn split_commas(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut prev_dash = false;
+    for tt in tokens {
+        match &tt {
+            TokenTree::Punct(p) if p.as_char() == ',' && depth == 0 => {
+                parts.push(Vec::new());
+                prev_dash = false;
+                continue;
+            }
+            TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+            // Not the `>` of an `->`.
+            TokenTree::Punct(p) if p.as_char() == '>' && !prev_dash => {
+                depth = depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        prev_dash = matches!(&tt, TokenTree::Punct(p)
+            if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+        parts.last_mut().unwrap().push(tt);
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Everything before the first top-level `=`, to drop defaults.
+fn before_eq(tokens: &[TokenTree]) -> &[TokenTree] {
+    let mut depth = 0usize;
+    for (i, tt) in tokens.iter().enumerate() {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+            TokenTree::Punct(p) if p.as_char() == '>' => depth = depth.saturating_sub(1),
+            TokenTree::Punct(p) if p.as_char() == '=' && depth == 0 => return &tokens[..i],
+            _ => {}
+        }
+    }
+    tokens
+}
+
+struct Cursor {
+    tokens: Vec<TokenTree>,
+    pos: usize,
+}
+
+impl Cursor {
+    fn new(stream: TokenStream) -> Self {
+        Self {
+            tokens: stream.into_iter().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&TokenTree> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<TokenTree> {
+        let tt = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tt
+    }
+
+    fn peek_punct(&self, c: char) -> bool {
+        self.peek().is_some_and(|tt| is_punct(tt, c))
+    }
+
+    fn peek_ident(&self, s: &str) -> bool {
+        self.peek().is_some_and(|tt| is_ident(tt, s))
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next() {
+            Some(TokenTree::Ident(i)) => Ok(i.to_string()),
+            other => Err(format!("expected an identifier, found {:?}", other).into()),
+        }
+    }
+
+    /// Parse outer attributes, returning the arguments of the `#[trace(..)]`
+    /// ones.
+    fn attrs(&mut self) -> Result<Vec<Group>> {
+        let mut trace = Vec::new();
+        while self.peek_punct('#') {
+            self.next();
+            let group = match self.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => g,
+                _ => return Err("expected an attribute".into()),
+            };
+            let mut attr = Cursor::new(group.stream());
+            if !attr.peek_ident("trace") {
+                continue;
+            }
+            attr.next();
+            match attr.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                    trace.push(g)
+                }
+                _ => return Err("expected arguments to `#[trace]`".into()),
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Skip a visibility, if any.
+    fn vis(&mut self) {
+        if self.peek_ident("pub") {
+            self.next();
+            if let Some(TokenTree::Group(g)) = self.peek() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    self.next();
+                }
+            }
+        }
+    }
+
+    /// Tokens up to (not including) the first that satisfies `stop`.
+    fn until(&mut self, stop: impl Fn(&TokenTree) -> bool) -> Vec<TokenTree> {
+        let mut out = Vec::new();
+        while let Some(tt) = self.peek() {
+            if stop(tt) {
+                break;
+            }
+            out.push(self.next().unwrap());
+        }
+        out
+    }
+}
+
+impl Mode {
+    fn parse(args: Group) -> Result<Mode> {
+        let mut args = Cursor::new(args.stream());
+        let span = args.peek().map_or_else(Span::call_site, TokenTree::span);
+        match args.ident()?.as_str() {
+            "skip" if args.peek().is_none() => Ok(Mode::Skip),
+            "unsafe" => match args.next() {
+                Some(TokenTree::Group(g))
+                    if g.delimiter() == Delimiter::Parenthesis && args.peek().is_none() =>
+                {
+                    let mut args = Cursor::new(g.stream());
+                    if !args.peek_ident("with") {
+                        return Err("expected `#[trace(unsafe(with = path))]`".into());
+                    }
+                    args.next();
+                    Ok(Mode::With(Self::parse_path(args)?))
+                }
+                _ => Err("expected `#[trace(unsafe(with = path))]`".into()),
+            },
+            "with" => Err(Error {
+                msg: "`#[trace(with = path)]` is no longer supported, write \
+                      `#[trace(unsafe(with = path))]` once `path` keeps the contract of `Trace`"
+                    .into(),
+                span,
+            }),
+            _ => Err("expected `#[trace(skip)]` or `#[trace(unsafe(with = path))]`".into()),
+        }
+    }
+
+    /// Parse the `= path` after a `with`.
+    fn parse_path(mut args: Cursor) -> Result<String> {
+        if !args.peek_punct('=') {
+            return Err("expected `#[trace(unsafe(with = path))]`".into());
+        }
+        args.next();
+        let path = args.until(|_| false);
+        if path.is_empty() {
+            return Err("expected a path after `with =`".into());
+        }
+        Ok(tokens_to_string(&path))
+    }
+}
+
+impl Fields {
+    fn parse(group: &Group) -> Result<Fields> {
+        let named = match group.delimiter() {
+            Delimiter::Brace => true,
+            Delimiter::Parenthesis => false,
+            _ => return Err("unexpected fields delimiter".into()),
+        };
+        let mut fields = Vec::new();
+        for tokens in split_commas(group.stream().into_iter().collect()) {
+            let mut field = Cursor { tokens, pos: 0 };
+            let mut mode = Mode::Trace;
+            for args in field.attrs()? {
+                mode = Mode::parse(args)?;
+            }
+            field.vis();
+            let name = if named { Some(field.ident()?) } else { None };
+            fields.push(Field { name, mode });
+        }
+        Ok(if named {
+            Fields::Named(fields)
+        } else {
+            Fields::Unnamed(fields)
+        })
+    }
+}
+
+impl Item {
+    fn parse(input: TokenStream) -> Result<Item> {
+        let mut input = Cursor::new(input);
+        let mut acyclic = false;
+        for args in input.attrs()? {
+            let mut args = Cursor::new(args.stream());
+            match args.next() {
+                Some(tt) if is_ident(&tt, "acyclic") && args.peek().is_none() => acyclic = true,
+                _ => return Err("expected `#[trace(acyclic)]`".into()),
+            }
+        }
+        input.vis();
+        let kind = input.ident()?;
+        let name = input.ident()?;
+        let params = Self::parse_generics(&mut input)?;
+        let mut where_clause = Vec::new();
+        let data = match kind.as_str() {
+            "struct" => {
+                if input.peek_ident("where") {
+                    input.next();
+                    where_clause = input.until(|tt| {
+                        is_punct(tt, ';')
+                            || matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)
+                    });
+                }
+                let fields = match input.next() {
+                    Some(TokenTree::Group(g)) => Fields::parse(&g)?,
+                    _ => Fields::Unit,
+                };
+                if input.peek_ident("where") {
+                    // After the fields of a tuple struct.
+                    input.next();
+                    where_clause = input.until(|tt| is_punct(tt, ';'));
+                }
+                Data::Struct(fields)
+            }
+            "enum" => {
+                if input.peek_ident("where") {
+                    input.next();
+                    where_clause = input.until(
+                        |tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace),
+                    );
+                }
+                match input.next() {
+                    Some(TokenTree::Group(g)) => Data::Enum(Self::parse_variants(&g)?),
+                    _ => return Err("expected the variants of the enum".into()),
+                }
+            }
+            "union" => return Err("`Trace` can't be derived for unions".into()),
+            _ => return Err(format!("expected a struct or an enum, found `{}`", kind).into()),
+        };
+        Ok(Item {
+            name,
+            acyclic,
+            params,
+            where_clause: tokens_to_string(&where_clause),
+            data,
+        })
+    }
+
+    fn parse_generics(input: &mut Cursor) -> Result<Vec<Param>> {
+        if !input.peek_punct('<') {
+            return Ok(Vec::new());
+        }
+        input.next();
+        let mut tokens = Vec::new();
+        let mut depth = 1usize;
+        let mut prev_dash = false;
+        loop {
+            let tt = input.next().ok_or("unterminated generics")?;
+            if is_punct(&tt, '<') {
+                depth += 1;
+            } else if is_punct(&tt, '>') && !prev_dash {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            prev_dash = matches!(&tt, TokenTree::Punct(p)
+                if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+            tokens.push(tt);
+        }
+        split_commas(tokens)
+            .into_iter()
+            .map(|param| {
+                let decl = before_eq(&param);
+                let (arg, is_type) = match &param[..] {
+                    [TokenTree::Punct(p), lifetime, ..] if p.as_char() == '\'' => {
+                        (format!("'{}", lifetime), false)
+                    }
+                    [konst, name, ..] if is_ident(konst, "const") => (name.to_string(), false),
+                    [TokenTree::Ident(name), ..] => (name.to_string(), true),
+                    _ => return Err("unexpected generic parameter".into()),
+                };
+                Ok(Param {
+                    decl: tokens_to_string(decl),
+                    arg,
+                    is_type,
+                })
+            })
+            .collect()
+    }
+
+    fn parse_variants(group: &Group) -> Result<Vec<Variant>> {
+        let mut input = Cursor::new(group.stream());
+        let mut variants = Vec::new();
+        while input.peek().is_some() {
+            input.attrs()?;
+            let name = input.ident()?;
+            let fields = match input.peek() {
+                Some(TokenTree::Group(g)) => {
+                    let fields = Fields::parse(g)?;
+                    input.next();
+                    fields
+                }
+                _ => Fields::Unit,
+            };
+            // Skip the discriminant, if any.
+            input.until(|tt| is_punct(tt, ','));
+            input.next();
+            variants.push(Variant { name, fields });
+        }
+        Ok(variants)
+    }
+
+    fn expand(&self) -> String {
+        let (impl_generics, ty_generics) = if self.params.is_empty() {
+            (String::new(), String::new())
+        } else {
+            let decls: Vec<_> = self.params.iter().map(|p| p.decl.as_str()).collect();
+            let args: Vec<_> = self.params.iter().map(|p| p.arg.as_str()).collect();
+            (
+                format!("<{}>", decls.join(", ")),
+                format!("<{}>", args.join(", ")),
+            )
+        };
+        let mut predicates = self.where_clause.trim().to_string();
+        if !predicates.is_empty() && !predicates.ends_with(',') {
+            predicates.push(',');
+        }
+        for param in self.params.iter().filter(|p| p.is_type) {
+            predicates.push_str(&format!(" {}: ::cc_bacon::Trace,", param.arg));
+        }
+        let body = match &self.data {
+            Data::Struct(fields) => {
+                let mut body = String::new();
+                let fields = match fields {
+                    Fields::Named(fields) | Fields::Unnamed(fields) => &fields[..],
+                    Fields::Unit => &[],
+                };
+                for (i, field) in fields.iter().enumerate() {
+                    let access = match &field.name {
+                        Some(name) => format!("&self.{}", name),
+                        None => format!("&self.{}", i),
+                    };
+                    body.push_str(&field.mode.call(&access));
+                }
+                body
+            }
+            Data::Enum(variants) if variants.is_empty() => "match *self {}".to_string(),
+            Data::Enum(variants) => {
+                let arms: String = variants.iter().map(Variant::arm).collect();
+                format!("match self {{ {} }}", arms)
+            }
+        };
+        let acyclic = if self.acyclic {
+            "fn is_acyclic() -> bool { true }"
+        } else {
+            ""
+        };
+        format!(
+            "unsafe impl{impl_generics} ::cc_bacon::Trace for {name}{ty_generics} where {predicates} {{
+                #[allow(unused_variables)]
+                fn trace(&self, tracer: &mut ::cc_bacon::Tracer) {{ {body} }}
+                {acyclic}
+            }}",
+            name = self.name,
+        )
+    }
+}
+
+impl Mode {
+    /// The statement tracing the field at `access`.
+    fn call(&self, access: &str) -> String {
+        match self {
+            Mode::Trace => format!("::cc_bacon::Trace::trace({}, tracer);", access),
+            Mode::Skip => String::new(),
+            Mode::With(path) => format!("{}({}, tracer);", path, access),
+        }
+    }
+}
+
+impl Variant {
+    /// The match arm tracing this variant's fields.
+    fn arm(&self) -> String {
+        let mut body = String::new();
+        let pattern = match &self.fields {
+            Fields::Named(fields) => {
+                let mut bindings = String::new();
+                for (i, field) in fields.iter().enumerate() {
+                    if let Mode::Skip = field.mode {
+                        continue;
+                    }
+                    let name = field.name.as_ref().unwrap();
+                    bindings.push_str(&format!("{}: __field{}, ", name, i));
+                    body.push_str(&field.mode.call(&format!("__field{}", i)));
+                }
+                format!("{{ {}.. }}", bindings)
+            }
+            Fields::Unnamed(fields) => {
+                let mut bindings = Vec::new();
+                for (i, field) in fields.iter().enumerate() {
+                    if let Mode::Skip = field.mode {
+                        bindings.push("_".to_string());
+                        continue;
+                    }
+                    bindings.push(format!("__field{}", i));
+                    body.push_str(&field.mode.call(&format!("__field{}", i)));
+                }
+                format!("({})", bindings.join(", "))
+            }
+            Fields::Unit => String::new(),
+        };
+        format!("Self::{}{} => {{ {} }}", self.name, pattern, body)
+    }
+}
@@ -0,0 +1,62 @@
+//! Recovering the concrete type of a `Cc<dyn CcAny>`.
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::mem::ManuallyDrop;
+
+use crate::{Cc, CcBoxPtr, Trace, Weak};
+
+/// [`Any`] for values that can go in a `Cc`, so that a heap of values of
+/// different types can be kept as `Cc<dyn CcAny>`, made with
+/// [`cc_coerce!`](crate::cc_coerce), and downcast back.
+///
+/// Downcasting looks at the type the box was allocated with, which is kept
+/// in its header, so it works for a `Weak` whose value is already gone too.
+pub trait CcAny: Trace + Any {}
+
+impl<T: Trace + Any> CcAny for T {}
+
+impl fmt::Debug for dyn CcAny {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CcAny { .. }")
+    }
+}
+
+impl Cc<dyn CcAny> {
+    /// Whether the value is a `T`.
+    pub fn is<T: CcAny>(&self) -> bool {
+        (self.metadata().vtable.type_id)() == TypeId::of::<T>()
+    }
+
+    /// The same box as a `Cc<T>`, or `self` back if the value isn't a `T`.
+    pub fn downcast<T: CcAny>(self) -> Result<Cc<T>, Self> {
+        if self.is::<T>() {
+            let this = ManuallyDrop::new(self);
+            Ok(Cc {
+                _ptr: this._ptr.cast(),
+            })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Weak<dyn CcAny> {
+    /// Whether the box was allocated for a `T`.
+    pub fn is<T: CcAny>(&self) -> bool {
+        self.inner()
+            .is_some_and(|inner| (inner.vtable.type_id)() == TypeId::of::<T>())
+    }
+
+    /// The same box as a `Weak<T>`, or `self` back if it wasn't allocated
+    /// for a `T`.
+    pub fn downcast<T: CcAny>(self) -> Result<Weak<T>, Self> {
+        if self.is::<T>() {
+            let this = ManuallyDrop::new(self);
+            Ok(Weak {
+                _ptr: this._ptr.cast(),
+            })
+        } else {
+            Err(self)
+        }
+    }
+}
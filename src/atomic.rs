@@ -0,0 +1,691 @@
+//! Thread-safe variant of the synchronous collector.
+//!
+//! [`Acc`] keeps its counts in atomics, so clones and drops on any thread
+//! take effect right away, and possible roots go into a buffer behind a lock.
+//! Collection runs the same algorithm as [`CycleCollector`](crate::CycleCollector)
+//! while the world is stopped: every thread touching an `Acc` graph does so
+//! inside a mutator region ([`AtomicCycleCollector::enter`]), and a collection
+//! waits until no thread is in one, then keeps them out until it is done.
+//!
+//! A thread that stays in a region for long should call
+//! [`Mutator::safepoint`] now and then, both to let pending collections run
+//! and to run the ones the [`CollectionPolicy`] asks for.
+//!
+//! A box released by one thread may still be looked at by another that has
+//! just given up its own reference, so its value is dropped right away but
+//! its memory is only given back by the next collection.
+use std::{
+    cell::{Cell, RefCell, UnsafeCell},
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::Deref,
+    ptr::NonNull,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::{
+    policy::Trigger, trace::traced_fully, CollectionPolicy, CollectionStats, Color, Trace, Tracer,
+};
+
+type NodePtr = NonNull<dyn Node>;
+
+/// Type erased view of an [`ABox`] for the collecting thread.
+trait Node: Send + Sync {
+    fn header(&self) -> &Header;
+
+    /// Collect the boxes of the same collector this one owns into `out`.
+    fn children(&self, out: &mut Vec<NodePtr>);
+
+    /// # Safety
+    ///
+    /// Must be called exactly once, after the strong count has reached zero.
+    unsafe fn drop_value(&self);
+}
+
+/// A [`Color`] that can be shared between threads.
+struct AtomicColor(AtomicU8);
+
+impl AtomicColor {
+    fn new(color: Color) -> Self {
+        Self(AtomicU8::new(color as u8))
+    }
+
+    #[inline]
+    fn get(&self) -> Color {
+        Color::ALL[self.0.load(Ordering::Relaxed) as usize]
+    }
+
+    #[inline]
+    fn set(&self, color: Color) {
+        self.0.store(color as u8, Ordering::Relaxed)
+    }
+}
+
+/// Collector state of an [`ABox`].
+///
+/// Colors and the buffered flag are only advisory outside of collections,
+/// which see them after the world mutex made every mutator's stores visible.
+struct Header {
+    strong: AtomicUsize,
+    color: AtomicColor,
+    buffered: AtomicBool,
+    root: Arc<AtomicCycleCollector>,
+}
+
+struct ABox<T: Trace> {
+    header: Header,
+    value: UnsafeCell<ManuallyDrop<T>>,
+}
+
+impl<T: Trace> ABox<T> {
+    #[inline(always)]
+    fn value(&self) -> &T {
+        unsafe { &*self.value.get() }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Node for ABox<T> {
+    fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn children(&self, out: &mut Vec<NodePtr>) {
+        EDGES.with(|edges| std::mem::swap(&mut *edges.borrow_mut(), out));
+        // `Acc::trace` pushes to `EDGES` rather than calling the tracer, so
+        // that local collectors never see our boxes.
+        self.value().trace(&mut |_| {});
+        EDGES.with(|edges| std::mem::swap(&mut *edges.borrow_mut(), out));
+    }
+
+    unsafe fn drop_value(&self) {
+        ManuallyDrop::drop(&mut *self.value.get());
+    }
+}
+
+// The value is only ever reached through `&T` from any thread.
+unsafe impl<T: Trace + Send + Sync> Sync for ABox<T> {}
+
+thread_local! {
+    /// Id of the collector this thread is running a collection of, if any.
+    static COLLECTING: Cell<usize> = const { Cell::new(0) };
+
+    /// Edges found by the `Node::children` call in progress.
+    static EDGES: RefCell<Vec<NodePtr>> = const { RefCell::new(Vec::new()) };
+
+    /// How deeply this thread is nested in the regions of each collector.
+    static REGIONS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[inline(always)]
+fn header<'a>(node: NodePtr) -> &'a Header {
+    unsafe { node.as_ref().header() }
+}
+
+/// The edges of every box a collection traced, taken the first time it
+/// traced the box.
+///
+/// A thread outside of a region may still change what a value owns while the
+/// world is stopped; every phase must see the same edges all the same, or the
+/// counts taken off by `mark_gray` are not the ones put back.
+#[derive(Default)]
+struct Snapshot {
+    edges: HashMap<*const (), Vec<NodePtr>>,
+    /// Boxes whose `trace` called `untraceable`, which are in use by whoever
+    /// holds what they couldn't reach.
+    untraceable: HashSet<*const ()>,
+}
+
+impl Snapshot {
+    fn children(&mut self, s: NodePtr) -> &[NodePtr] {
+        let key = s.as_ptr() as *const ();
+        if !self.edges.contains_key(&key) {
+            let mut out = Vec::new();
+            if !traced_fully(|| unsafe { s.as_ref().children(&mut out) }) {
+                self.untraceable.insert(key);
+            }
+            self.edges.insert(key, out);
+        }
+        &self.edges[&key]
+    }
+
+    fn is_untraceable(&self, s: NodePtr) -> bool {
+        self.untraceable.contains(&(s.as_ptr() as *const ()))
+    }
+}
+
+/// Give back the memory of a box whose value has already been dropped.
+unsafe fn deallocate(s: NodePtr) {
+    // `ABox` keeps its value in a `ManuallyDrop`, so this only drops the
+    // header.
+    drop(Box::from_raw(s.as_ptr()));
+}
+
+struct World {
+    /// Threads currently in a mutator region.
+    active: usize,
+    /// Whether a collection is waiting for, or running in, a stopped world.
+    stopping: bool,
+}
+
+struct Roots {
+    buffer: Vec<NodePtr>,
+    trigger: Trigger,
+}
+
+// Only boxes of `Send + Sync` values end up in the buffer.
+unsafe impl Send for Roots {}
+
+/// A cycle collector shared by the [`Acc`]s of many threads.
+pub struct AtomicCycleCollector {
+    id: usize,
+    roots: Mutex<Roots>,
+    world: Mutex<World>,
+    /// Signalled whenever a thread leaves its region or a collection ends.
+    cond: Condvar,
+    /// Lock free copy of `World::stopping` for `safepoint`.
+    stopping: AtomicBool,
+    stats: Mutex<CollectionStats>,
+}
+
+impl Debug for AtomicCycleCollector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtomicCycleCollector")
+            .field("roots", &self.roots.lock().unwrap().buffer.len())
+            .field("policy", &self.policy())
+            .finish()
+    }
+}
+
+impl Default for AtomicCycleCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtomicCycleCollector {
+    pub fn new() -> Self {
+        Self::with_policy(CollectionPolicy::default())
+    }
+
+    pub fn with_policy(policy: CollectionPolicy) -> Self {
+        static IDS: AtomicUsize = AtomicUsize::new(1);
+        Self {
+            id: IDS.fetch_add(1, Ordering::Relaxed),
+            roots: Mutex::new(Roots {
+                buffer: Vec::new(),
+                trigger: Trigger::new(policy),
+            }),
+            world: Mutex::new(World {
+                active: 0,
+                stopping: false,
+            }),
+            cond: Condvar::new(),
+            stopping: AtomicBool::new(false),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn policy(&self) -> CollectionPolicy {
+        self.roots.lock().unwrap().trigger.policy()
+    }
+
+    pub fn set_policy(&self, policy: CollectionPolicy) {
+        self.roots.lock().unwrap().trigger.set_policy(policy);
+    }
+
+    /// Cumulative stats of every `collect_cycles` run by this collector.
+    pub fn stats(&self) -> CollectionStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Enter a mutator region, waiting for a running collection to finish
+    /// first. Collections wait for the region to be left.
+    ///
+    /// Reading or writing the `Acc`s stored in other values, e.g. behind a
+    /// `RwLock`, must happen inside a region so that no collection traces the
+    /// graph halfway through the change. Creating, cloning and dropping an
+    /// `Acc` enters one on its own if needed. Regions nest.
+    pub fn enter(&self) -> Mutator<'_> {
+        if self.region_depth(1) == 0 {
+            self.enter_world();
+        }
+        Mutator {
+            collector: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Add `delta` to this thread's region depth, returning the old one.
+    fn region_depth(&self, delta: isize) -> usize {
+        REGIONS.with(|regions| {
+            let mut regions = regions.borrow_mut();
+            let i = match regions.iter().position(|(id, _)| *id == self.id) {
+                Some(i) => i,
+                None => {
+                    regions.push((self.id, 0));
+                    regions.len() - 1
+                }
+            };
+            let depth = regions[i].1;
+            regions[i].1 = depth.wrapping_add_signed(delta);
+            if regions[i].1 == 0 {
+                regions.swap_remove(i);
+            }
+            depth
+        })
+    }
+
+    fn in_region(&self) -> bool {
+        REGIONS.with(|regions| regions.borrow().iter().any(|(id, _)| *id == self.id))
+    }
+
+    fn enter_world(&self) {
+        let mut world = self.world.lock().unwrap();
+        while world.stopping {
+            world = self.cond.wait(world).unwrap();
+        }
+        world.active += 1;
+    }
+
+    fn leave_world(&self) {
+        let mut world = self.world.lock().unwrap();
+        world.active -= 1;
+        self.cond.notify_all();
+    }
+
+    /// Run `f` inside a region, which the calling thread may already be in.
+    #[inline]
+    fn in_mutator<R>(&self, f: impl FnOnce() -> R) -> R {
+        if COLLECTING.with(|c| c.get()) == self.id {
+            // Called from a value dropped by our own collection.
+            return f();
+        }
+        let _mutator = self.enter();
+        f()
+    }
+
+    /// Run `collect_cycles` if the [`CollectionPolicy`] asks for it.
+    pub fn maybe_collect(&self) {
+        let should_collect = {
+            let roots = self.roots.lock().unwrap();
+            roots.trigger.should_collect(roots.buffer.len())
+        };
+        if should_collect && COLLECTING.with(|c| c.get()) == 0 {
+            self.collect_cycles();
+        }
+    }
+
+    /// Stop the world and reclaim every garbage cycle reachable from the
+    /// roots buffer.
+    ///
+    /// May be called from inside a region, which then counts as stopped.
+    /// If another thread is already collecting, waits for it to finish and
+    /// collects again.
+    pub fn collect_cycles(&self) -> CollectionStats {
+        let inside = self.in_region();
+        let mut world = self.world.lock().unwrap();
+        if inside {
+            world.active -= 1;
+            self.cond.notify_all();
+        }
+        while world.stopping {
+            world = self.cond.wait(world).unwrap();
+        }
+        world.stopping = true;
+        self.stopping.store(true, Ordering::Relaxed);
+        while world.active > 0 {
+            world = self.cond.wait(world).unwrap();
+        }
+        drop(world);
+
+        let previous = COLLECTING.with(|c| c.replace(self.id));
+        let stats = self.collect_stopped();
+        COLLECTING.with(|c| c.set(previous));
+
+        let mut world = self.world.lock().unwrap();
+        world.stopping = false;
+        self.stopping.store(false, Ordering::Relaxed);
+        if inside {
+            world.active += 1;
+        }
+        self.cond.notify_all();
+        stats
+    }
+
+    fn collect_stopped(&self) -> CollectionStats {
+        let start = Instant::now();
+        let roots = std::mem::take(&mut self.roots.lock().unwrap().buffer);
+        let mut stats = CollectionStats {
+            collections: 1,
+            roots: roots.len(),
+            ..Default::default()
+        };
+        let mut snapshot = Snapshot::default();
+        let roots = Self::mark_roots(roots, &mut snapshot, &mut stats);
+        for &s in &roots {
+            Self::scan(s, &mut snapshot);
+        }
+        Self::collect_roots(roots, &mut snapshot, &mut stats);
+        stats.untraceable = snapshot.untraceable.len();
+        stats.elapsed = start.elapsed();
+
+        self.roots
+            .lock()
+            .unwrap()
+            .trigger
+            .collected(stats.roots, stats.freed);
+        *self.stats.lock().unwrap() += stats;
+        stats
+    }
+
+    fn increment(s: NodePtr) {
+        let h = header(s);
+        h.strong.fetch_add(1, Ordering::Relaxed);
+        if h.color.get() != Color::Green {
+            h.color.set(Color::Black);
+        }
+    }
+
+    fn decrement(s: NodePtr) {
+        let h = header(s);
+        if h.strong.fetch_sub(1, Ordering::Release) == 1 {
+            atomic::fence(Ordering::Acquire);
+            Self::release(s);
+        } else {
+            Self::possible_root(s);
+        }
+    }
+
+    /// Drop the value of a box whose strong count just reached zero, and
+    /// hand it to the next collection to free.
+    fn release(s: NodePtr) {
+        let h = header(s);
+        h.color.set(Color::Black);
+        unsafe { s.as_ref().drop_value() };
+        if !h.buffered.swap(true, Ordering::AcqRel) {
+            h.root.roots.lock().unwrap().buffer.push(s);
+        }
+    }
+
+    fn possible_root(s: NodePtr) {
+        let h = header(s);
+        if h.color.get() != Color::Purple && h.color.get() != Color::Green {
+            h.color.set(Color::Purple);
+            if !h.buffered.swap(true, Ordering::AcqRel) {
+                h.root.roots.lock().unwrap().buffer.push(s);
+            }
+        }
+    }
+
+    fn mark_roots(
+        mut roots: Vec<NodePtr>,
+        snapshot: &mut Snapshot,
+        stats: &mut CollectionStats,
+    ) -> Vec<NodePtr> {
+        // Released boxes are told apart by their count, so free them before
+        // trial deletion brings live ones down to zero too.
+        roots.retain(|&s| {
+            let h = header(s);
+            if h.strong.load(Ordering::Relaxed) > 0 {
+                return true;
+            }
+            h.buffered.store(false, Ordering::Relaxed);
+            stats.freed += 1;
+            stats.bytes_freed += std::mem::size_of_val(unsafe { s.as_ref() });
+            unsafe { deallocate(s) };
+            false
+        });
+        roots.retain(|&s| {
+            let h = header(s);
+            if h.color.get() == Color::Purple {
+                Self::mark_gray(s, snapshot, stats);
+                true
+            } else {
+                h.buffered.store(false, Ordering::Relaxed);
+                false
+            }
+        });
+        roots
+    }
+
+    fn mark_gray(s: NodePtr, snapshot: &mut Snapshot, stats: &mut CollectionStats) {
+        if header(s).color.get() == Color::Gray {
+            return;
+        }
+        header(s).color.set(Color::Gray);
+        stats.marked_gray += 1;
+        let mut stack = vec![s];
+        while let Some(s) = stack.pop() {
+            for &t in snapshot.children(s) {
+                let h = header(t);
+                if h.color.get() == Color::Green {
+                    continue;
+                }
+                h.strong.fetch_sub(1, Ordering::Relaxed);
+                if h.color.get() != Color::Gray {
+                    h.color.set(Color::Gray);
+                    stats.marked_gray += 1;
+                    stack.push(t);
+                }
+            }
+        }
+    }
+
+    fn scan(s: NodePtr, snapshot: &mut Snapshot) {
+        let mut stack = vec![s];
+        while let Some(s) = stack.pop() {
+            let h = header(s);
+            if h.color.get() == Color::Gray {
+                if h.strong.load(Ordering::Relaxed) > 0 || snapshot.is_untraceable(s) {
+                    Self::scan_black(s, snapshot);
+                } else {
+                    h.color.set(Color::White);
+                    stack.extend(
+                        snapshot
+                            .children(s)
+                            .iter()
+                            .filter(|&&t| header(t).color.get() != Color::Green),
+                    );
+                }
+            }
+        }
+    }
+
+    fn scan_black(s: NodePtr, snapshot: &mut Snapshot) {
+        header(s).color.set(Color::Black);
+        let mut stack = vec![s];
+        while let Some(s) = stack.pop() {
+            for &t in snapshot.children(s) {
+                let h = header(t);
+                if h.color.get() == Color::Green {
+                    continue;
+                }
+                h.strong.fetch_add(1, Ordering::Relaxed);
+                if h.color.get() != Color::Black {
+                    h.color.set(Color::Black);
+                    stack.push(t);
+                }
+            }
+        }
+    }
+
+    /// Tear down the garbage cycles the same way `CycleCollector` does.
+    fn collect_roots(roots: Vec<NodePtr>, snapshot: &mut Snapshot, stats: &mut CollectionStats) {
+        let mut white = Vec::new();
+        for s in roots {
+            header(s).buffered.store(false, Ordering::Relaxed);
+            Self::collect_white(s, snapshot, &mut white);
+        }
+        for &s in &white {
+            for &t in snapshot.children(s) {
+                let h = header(t);
+                // Members of the garbage sit at zero, live objects never do.
+                // Green ones were never taken off.
+                if h.strong.load(Ordering::Relaxed) > 0 && h.color.get() != Color::Green {
+                    h.strong.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        for &s in &white {
+            unsafe { s.as_ref().drop_value() };
+        }
+        stats.white += white.len();
+        for s in white {
+            stats.freed += 1;
+            stats.bytes_freed += std::mem::size_of_val(unsafe { s.as_ref() });
+            unsafe { deallocate(s) };
+        }
+    }
+
+    fn collect_white(s: NodePtr, snapshot: &mut Snapshot, white: &mut Vec<NodePtr>) {
+        let is_white =
+            |h: &Header| h.color.get() == Color::White && !h.buffered.load(Ordering::Relaxed);
+        if !is_white(header(s)) {
+            return;
+        }
+        header(s).color.set(Color::Black);
+        let mut stack = vec![s];
+        while let Some(s) = stack.pop() {
+            white.push(s);
+            for &t in snapshot.children(s) {
+                if is_white(header(t)) {
+                    header(t).color.set(Color::Black);
+                    stack.push(t);
+                }
+            }
+        }
+    }
+}
+
+/// A thread's stay in a mutator region of an [`AtomicCycleCollector`], see
+/// [`AtomicCycleCollector::enter`].
+pub struct Mutator<'a> {
+    collector: &'a AtomicCycleCollector,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Mutator<'_> {
+    /// Let a pending collection run, then collect if the policy asks for it.
+    ///
+    /// Everything borrowed from the `Acc` graph must be given up first, as
+    /// the collection may free it.
+    pub fn safepoint(&mut self) {
+        if self.collector.stopping.load(Ordering::Relaxed) {
+            self.collector.leave_world();
+            self.collector.enter_world();
+        }
+        self.collector.maybe_collect();
+    }
+}
+
+impl Drop for Mutator<'_> {
+    fn drop(&mut self) {
+        if self.collector.region_depth(-1) == 1 {
+            self.collector.leave_world();
+        }
+    }
+}
+
+/// A reference-counted pointer that can be shared between threads, collected
+/// by an [`AtomicCycleCollector`].
+pub struct Acc<T: Trace + Send + Sync + 'static> {
+    _ptr: NonNull<ABox<T>>,
+}
+
+unsafe impl<T: Trace + Send + Sync + 'static> Send for Acc<T> {}
+unsafe impl<T: Trace + Send + Sync + 'static> Sync for Acc<T> {}
+
+impl<T: Trace + Send + Sync + 'static> Acc<T> {
+    pub fn new(value: T, collector: &Arc<AtomicCycleCollector>) -> Acc<T> {
+        collector
+            .roots
+            .lock()
+            .unwrap()
+            .trigger
+            .note_allocation(std::mem::size_of::<ABox<T>>());
+        let boxed = Box::new(ABox {
+            header: Header {
+                strong: 1.into(),
+                color: AtomicColor::new(if T::is_acyclic() {
+                    Color::Green
+                } else {
+                    Color::Black
+                }),
+                buffered: false.into(),
+                root: collector.clone(),
+            },
+            value: UnsafeCell::new(ManuallyDrop::new(value)),
+        });
+        unsafe {
+            Acc {
+                _ptr: NonNull::new_unchecked(Box::into_raw(boxed)),
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &ABox<T> {
+        unsafe { self._ptr.as_ref() }
+    }
+
+    /// Return the strong reference count.
+    pub fn strong(&self) -> usize {
+        self.inner().header.strong.load(Ordering::Relaxed)
+    }
+
+    fn node(&self) -> NodePtr {
+        self._ptr as NodePtr
+    }
+}
+
+unsafe impl<T: Trace + Send + Sync + 'static> Trace for Acc<T> {
+    fn trace(&self, _tracer: &mut Tracer) {
+        // Only visible to a collection of our own collector; local
+        // collectors must not touch atomic counts.
+        if COLLECTING.with(|c| c.get()) == self.inner().header.root.id {
+            EDGES.with(|edges| edges.borrow_mut().push(self.node()));
+        }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Deref for Acc<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        self.inner().value()
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Clone for Acc<T> {
+    fn clone(&self) -> Self {
+        let root = &self.inner().header.root;
+        root.in_mutator(|| AtomicCycleCollector::increment(self.node()));
+        Acc { _ptr: self._ptr }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Drop for Acc<T> {
+    fn drop(&mut self) {
+        // `decrement` may release the box, so hold on to the collector first.
+        let root = self.inner().header.root.clone();
+        if COLLECTING.with(|c| c.get()) == root.id {
+            // Dropped along with a value by our own collection: members of
+            // the garbage sit at zero and must be left alone.
+            if self.strong() > 0 {
+                AtomicCycleCollector::decrement(self.node());
+            }
+            return;
+        }
+        root.in_mutator(|| AtomicCycleCollector::decrement(self.node()));
+        if !root.in_region() {
+            root.maybe_collect();
+        }
+    }
+}
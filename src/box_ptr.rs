@@ -1,4 +1,4 @@
-use crate::{CcBoxMetaData, CcPtr, Color, CycleCollector, Trace};
+use crate::{CcBoxMetaData, CcPtr, CollectionStats, Color, CycleCollector, Trace};
 
 pub trait CcBoxPtr: Trace {
     /// Get this `CcBoxPtr`'s [`CcBoxMetaData`].
@@ -6,58 +6,69 @@ pub trait CcBoxPtr: Trace {
 
     fn get_ptr(&self) -> CcPtr;
 
+    /// Drop the value stored in the box in place, leaving the allocation and
+    /// its metadata alive for any remaining weak pointers.
+    ///
+    /// # Safety
+    ///
+    /// Must be called exactly once, after the strong count has reached zero.
+    unsafe fn drop_value(&self);
+
+    /// Name of the type of the value stored in the box, for diagnostics.
+    fn type_name(&self) -> &'static str;
+
     /// Get the color of this node.
     #[inline]
     fn color(&self) -> Color {
-        self.metadata().color.get()
+        self.metadata().color()
     }
 
     /// Return true if this node is in the buffer of possible cycle roots, false
     /// otherwise.
     #[inline]
     fn buffered(&self) -> bool {
-        self.metadata().buffered.get()
+        self.metadata().buffered()
     }
 
     /// Return the strong reference count.
     #[inline]
     fn strong(&self) -> usize {
-        self.metadata().strong.get()
+        self.metadata().strong()
     }
 
     /// Only Increment this node's strong reference count.
     #[inline]
     fn inc_strong(&self) {
-        self.metadata().strong.set(self.strong() + 1);
+        self.metadata().set_strong(self.strong() + 1);
     }
 
     /// Only dec strong ref and do nothing more
     #[inline]
     fn dec_strong(&self) {
-        self.metadata().strong.set(self.strong() - 1);
+        self.metadata().set_strong(self.strong() - 1);
     }
 
     /// Get this node's weak reference count, including the "strong weak"
     /// reference.
     #[inline]
     fn weak(&self) -> usize {
-        self.metadata().weak.get()
+        self.metadata().weak()
     }
 
     /// Increment this node's weak reference count.
     #[inline]
     fn inc_weak(&self) {
-        self.metadata().weak.set(self.weak() + 1);
+        self.metadata().set_weak(self.weak() + 1);
     }
 
     /// Decrement this node's weak reference count.
     #[inline]
     fn dec_weak(&self) {
-        self.metadata().weak.set(self.weak() - 1);
+        self.metadata().set_weak(self.weak() - 1);
     }
 }
 
 /// .
-pub fn collect_cycles(roots: &CycleCollector) {
-    roots.collect_cycles();
+pub fn collect_cycles(roots: &CycleCollector) -> CollectionStats {
+    roots.collect_cycles()
 }
@@ -0,0 +1,142 @@
+//! Checked mode: catch `Trace` impls that break their contract before trial
+//! deletion acts on what they report.
+//!
+//! Over-reporting a box makes `mark_gray` take more off its strong count than
+//! there are references, and tracing differently from one call to the next
+//! makes `scan` put back something else than what was taken off. Either way
+//! the collector would end up freeing live boxes.
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::trace::traced_fully;
+use crate::{CcBoxMetaData, CcBoxPtr, CcPtr, Color, CycleCollector, Trace};
+
+/// A `Trace` impl found breaking its contract by
+/// [`CycleCollector::check_traces`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceError {
+    /// The box was reported more times than it has strong references.
+    OverReported {
+        /// Type of the value in the box.
+        type_name: &'static str,
+        ptr: CcPtr,
+        reported: usize,
+        strong: usize,
+        /// Types of the values whose `trace` reported it.
+        reported_by: Vec<&'static str>,
+    },
+    /// Two calls of the `trace` of the box's value reported different boxes.
+    Nondeterministic { type_name: &'static str, ptr: CcPtr },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::OverReported {
+                type_name,
+                ptr,
+                reported,
+                strong,
+                reported_by,
+            } => write!(
+                f,
+                "Cc<{}> at {:p} was traced {} times with a strong count of {}, by {}",
+                type_name,
+                ptr,
+                reported,
+                strong,
+                reported_by.join(", "),
+            ),
+            TraceError::Nondeterministic { type_name, ptr } => write!(
+                f,
+                "tracing {} at {:p} twice reported different Ccs",
+                type_name, ptr
+            ),
+        }
+    }
+}
+
+impl Error for TraceError {}
+
+impl CycleCollector {
+    /// Trace everything the next collection would, without changing any
+    /// count, and check that the `Trace` impls involved report no box more
+    /// times than it has strong references and the same boxes every time.
+    ///
+    /// Does nothing in the middle of a collection or release.
+    pub fn check_traces(&self) -> Result<(), TraceError> {
+        if self.collecting.get() || self.releasing.get() {
+            return Ok(());
+        }
+        let _guard = self.begin_collection();
+        let checked = self.tracing(|| self.check_roots());
+        self.end_collection();
+        checked
+    }
+
+    /// Walk the subgraph `mark_gray` would, from the Purple roots.
+    pub(crate) fn check_roots(&self) -> Result<(), TraceError> {
+        let edges = |s: &CcBoxMetaData| {
+            let mut edges = Vec::new();
+            // Whatever it can't reach now, it can't during the collection
+            // either.
+            traced_fully(|| {
+                s.trace(&mut |ch| {
+                    if ch.color() != Color::Green {
+                        edges.push(ch.get_ptr());
+                    }
+                })
+            });
+            edges
+        };
+        let mut reported: HashMap<CcPtr, Vec<CcPtr>> = HashMap::new();
+        let roots = self.roots.borrow().clone();
+        let mut stack: Vec<CcPtr> = roots
+            .into_iter()
+            .filter(|s| unsafe { s.as_ref() }.color() == Color::Purple)
+            .collect();
+        let mut seen: HashSet<CcPtr> = stack.iter().copied().collect();
+        while let Some(s) = stack.pop() {
+            let obj = unsafe { s.as_ref() };
+            let first = edges(obj);
+            let mut second = edges(obj);
+            let mut sorted = first.clone();
+            sorted.sort();
+            second.sort();
+            if sorted != second {
+                return Err(TraceError::Nondeterministic {
+                    type_name: obj.type_name(),
+                    ptr: s,
+                });
+            }
+            for ch in first {
+                reported.entry(ch).or_default().push(s);
+                // The value of a released box is gone, there is nothing to
+                // trace; that it was reported at all is caught below.
+                if unsafe { ch.as_ref() }.strong() > 0 && seen.insert(ch) {
+                    stack.push(ch);
+                }
+            }
+        }
+        for (s, by) in reported {
+            let obj = unsafe { s.as_ref() };
+            if by.len() > obj.strong() {
+                let mut reported_by: Vec<_> = by
+                    .iter()
+                    .map(|s| unsafe { s.as_ref() }.type_name())
+                    .collect();
+                reported_by.sort_unstable();
+                reported_by.dedup();
+                return Err(TraceError::OverReported {
+                    type_name: obj.type_name(),
+                    ptr: s,
+                    reported: by.len(),
+                    strong: obj.strong(),
+                    reported_by,
+                });
+            }
+        }
+        Ok(())
+    }
+}
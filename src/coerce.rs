@@ -0,0 +1,63 @@
+//! Unsizing a `Cc`, for `Cc<dyn Trait>`s.
+//!
+//! Stable Rust doesn't let `Cc` implement `CoerceUnsized`, so [`cc_coerce!`]
+//! does the coercion on the raw box pointer instead. The box keeps the
+//! vtable of the type it was allocated with in its header, so the collector
+//! traces and drops it the same way after the coercion.
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+
+use crate::{Cc, CcBox, Trace};
+
+/// Turn a `Cc<T>` into a `Cc<U>`, where `T` coerces to the unsized `U`, like
+/// `Cc<dyn Trait>` from `Cc<Concrete>` or `Cc<[T]>` from `Cc<[T; N]>`.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use cc_bacon::{cc_coerce, Cc, CycleCollector, Trace};
+/// trait Shape: Trace {
+///     fn area(&self) -> f64;
+/// }
+///
+/// struct Square(f64);
+///
+/// unsafe impl Trace for Square {
+///     fn trace(&self, _tracer: &mut cc_bacon::Tracer) {}
+/// }
+///
+/// impl Shape for Square {
+///     fn area(&self) -> f64 {
+///         self.0 * self.0
+///     }
+/// }
+///
+/// let roots = Arc::new(CycleCollector::new());
+/// let shape: Cc<dyn Shape> = cc_coerce!(Cc::new(Square(2.0), &roots) => dyn Shape);
+/// assert_eq!(shape.area(), 4.0);
+/// ```
+#[macro_export]
+macro_rules! cc_coerce {
+    ($cc:expr => $ty:ty) => {{
+        let ptr = $crate::Cc::__into_box_ptr($cc);
+        // A plain coercion, so that nothing but unsizing type checks.
+        let ptr: *mut $crate::CcBox<$ty> = ptr;
+        unsafe { $crate::Cc::<$ty>::__from_box_ptr(ptr) }
+    }};
+}
+
+impl<T: ?Sized + Trace> Cc<T> {
+    #[doc(hidden)]
+    pub fn __into_box_ptr(this: Self) -> *mut CcBox<T> {
+        ManuallyDrop::new(this)._ptr.as_ptr()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from [`Cc::__into_box_ptr`], possibly unsized.
+    #[doc(hidden)]
+    pub unsafe fn __from_box_ptr(ptr: *mut CcBox<T>) -> Self {
+        Cc {
+            _ptr: NonNull::new_unchecked(ptr),
+        }
+    }
+}
@@ -1,18 +1,86 @@
 //! impl bacon's cycle collector: <http://link.springer.com/10.1007/3-540-45337-7_12>
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::ptr::NonNull;
+use std::time::Instant;
 
-use crate::{dealloc::free, CcBoxPtr, Color};
+use crate::{
+    dealloc::{free, release_weak},
+    finalize::Finalizers,
+    policy::Trigger,
+    registry::CollectorId,
+    trace::traced_fully,
+    CcBoxMetaData, CcBoxPtr, CollectionPolicy, CollectionStats, Color, Trace,
+};
 // TODO: understand NonNull can be safe?
-pub type CcPtr = NonNull<dyn CcBoxPtr>;
+pub type CcPtr = NonNull<CcBoxMetaData>;
 
-/// one CycleCollector for one virtual Machine
+/// Clears one of the collector's busy flags if a `Drop` impl, finalizer or
+/// `Trace` impl panics while it is set, so that the collector doesn't stay
+/// busy, and never collect again, once the panic is caught.
+pub(crate) struct UnwindGuard<'a>(&'a Cell<bool>);
+
+impl Drop for UnwindGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.set(false);
+        }
+    }
+}
+
+/// A pending step of one of the paper's recursive procedures, kept on the
+/// collector's explicit work stack so that collection depth is bounded by the
+/// heap rather than the thread stack.
+#[derive(Clone, Copy)]
+enum Work {
+    /// The node is already Gray; decrement its children and gray them.
+    MarkGray(CcPtr),
+    Scan(CcPtr),
+    /// The node is already Black; restore its children's counts and blacken
+    /// them.
+    ScanBlack(CcPtr),
+    /// The node is white and has already been recolored; collect its white
+    /// children.
+    CollectWhite(CcPtr),
+}
 
+/// one CycleCollector for one virtual Machine
 pub struct CycleCollector {
-    roots: RefCell<Vec<CcPtr>>,
+    pub(crate) roots: RefCell<Vec<CcPtr>>,
+    trigger: Trigger,
+    work: RefCell<Vec<Work>>,
+    /// Boxes whose strong count reached zero and whose value still has to be
+    /// dropped, see `release`.
+    releases: RefCell<Vec<CcPtr>>,
+    pub(crate) releasing: Cell<bool>,
+    pub(crate) collecting: Cell<bool>,
+    /// Set while the collector is calling `Trace` impls, see
+    /// `defer_decrement`.
+    tracing: Cell<bool>,
+    /// Boxes whose strong count `Cc`s dropped while tracing still have to
+    /// take off.
+    deferred: RefCell<Vec<CcPtr>>,
+    /// Whether `collect_cycles` was called while a collection or release was
+    /// in progress; it runs once that is done.
+    requested: Cell<bool>,
+    /// Boxes found untraceable by `mark_gray` during this collection, which
+    /// `scan` keeps Black.
+    untraceable: RefCell<HashSet<CcPtr>>,
+    /// Whether every collection checks the `Trace` impls first, see
+    /// `set_checked`.
+    checked: Cell<bool>,
+    pub(crate) finalizers: Finalizers,
+    /// Sum of the stats of every collection so far.
+    stats: Cell<CollectionStats>,
+    /// Index in this thread's registry while any box is allocated.
+    pub(crate) id: Cell<Option<CollectorId>>,
+    /// Number of boxes allocated, including ones kept by weak pointers only.
+    pub(crate) live: Cell<usize>,
+    /// Strong counts too large for their box's header, by metadata address.
+    pub(crate) overflow: RefCell<HashMap<*const CcBoxMetaData, usize>>,
 }
 
 impl Debug for CycleCollector {
@@ -36,16 +104,91 @@ impl Default for CycleCollector {
 
 impl CycleCollector {
     pub fn new() -> Self {
+        Self::with_policy(CollectionPolicy::default())
+    }
+
+    pub fn with_policy(policy: CollectionPolicy) -> Self {
         Self {
             roots: Vec::new().into(),
+            trigger: Trigger::new(policy),
+            work: Vec::new().into(),
+            releases: Vec::new().into(),
+            releasing: false.into(),
+            collecting: false.into(),
+            tracing: false.into(),
+            deferred: Vec::new().into(),
+            requested: false.into(),
+            untraceable: Default::default(),
+            checked: false.into(),
+            finalizers: Default::default(),
+            stats: Default::default(),
+            id: Cell::new(None),
+            live: Cell::new(0),
+            overflow: Default::default(),
+        }
+    }
+
+    pub fn policy(&self) -> CollectionPolicy {
+        self.trigger.policy()
+    }
+
+    pub fn set_policy(&self, policy: CollectionPolicy) {
+        self.trigger.set_policy(policy);
+    }
+
+    /// Whether collections check the `Trace` impls they are about to rely on.
+    pub fn checked(&self) -> bool {
+        self.checked.get()
+    }
+
+    /// Have every collection run [`check_traces`](Self::check_traces) before
+    /// touching any count, and panic with what it found instead of going on
+    /// with a broken `Trace` impl; and [`verify`](Self::verify) the heap
+    /// once it is done. Slow, meant for debugging.
+    pub fn set_checked(&self, checked: bool) {
+        self.checked.set(checked);
+    }
+
+    /// Cumulative stats of every `collect_cycles` run by this collector.
+    pub fn stats(&self) -> CollectionStats {
+        self.stats.get()
+    }
+
+    /// Record a new `Cc` box of `bytes` bytes for the allocation based
+    /// policies.
+    #[inline]
+    pub(crate) fn note_allocation(&self, bytes: usize) {
+        self.trigger.note_allocation(bytes);
+    }
+
+    /// Run `collect_cycles` if the [`CollectionPolicy`] asks for it.
+    ///
+    /// Never collects in the middle of a `release`, whose pending boxes look
+    /// like garbage but still own their children, nor in the middle of a
+    /// collection, e.g. from a finalizer.
+    #[inline]
+    pub fn maybe_collect(&self) {
+        if !self.releasing.get()
+            && !self.collecting.get()
+            && self.trigger.should_collect(self.roots.borrow().len())
+        {
+            self.collect_cycles();
         }
     }
 
-    /// cresponding to `Increment(S)`in paper, change color to Black
+    /// cresponding to `Increment(S)`in paper, change color to Black unless
+    /// it's Green
     #[inline]
     pub fn increment(zelf: &dyn CcBoxPtr) {
+        assert!(
+            !zelf.metadata().torn_down(),
+            "revived a {} being collected",
+            zelf.type_name()
+        );
         zelf.inc_strong();
-        zelf.metadata().color.set(Color::Black);
+        if zelf.color() != Color::Green {
+            zelf.metadata().set_color(Color::Black);
+        }
     }
 
     /// Decrement this node's strong reference count.
@@ -53,42 +196,101 @@ impl CycleCollector {
     /// crosponding to `Decrement(S)`in paper
     #[inline]
     pub fn decrement(zelf: &dyn CcBoxPtr) {
-        #[cfg(test)]
-        dbg!("Before dec: ", zelf.strong());
-        #[cfg(test)]
-        dbg!(zelf.get_ptr());
+        if zelf.metadata().torn_down() {
+            // A `Cc` moved out of a garbage cycle's value; the value is gone.
+            if zelf.strong() > 0 {
+                zelf.dec_strong();
+                if zelf.strong() == 0 {
+                    if zelf.buffered() {
+                        // `mark_roots` frees it.
+                        zelf.metadata().set_color(Color::Black);
+                    } else {
+                        unsafe { free(zelf.get_ptr()) };
+                    }
+                }
+            }
+            return;
+        }
         if zelf.strong() > 0 {
             zelf.dec_strong();
             if zelf.strong() == 0 {
-                Self::release(zelf);
+                zelf.metadata().root().release(zelf.get_ptr());
             } else {
                 Self::possible_root(zelf);
             }
         }
-        #[cfg(test)]
-        dbg!("After dec: ", zelf.strong());
     }
 
-    /// .
-    fn release(zelf: &dyn CcBoxPtr) {
-        debug_assert_eq!(zelf.strong(), 0);
-        // self.trace(&mut |ch| ch.decrement());
-        let obj = unsafe { zelf.get_ptr().as_ref() };
-        obj.trace(&mut |ch| Self::decrement(ch));
-        zelf.metadata().color.set(Color::Black);
-        if !zelf.buffered() {
-            unsafe {
-                free(zelf.get_ptr());
+    /// Release a box whose strong count just reached zero.
+    ///
+    /// Dropping its value drops the `Cc`s it owns, which may release their
+    /// boxes in turn. Those are queued up and handled by the outermost call
+    /// instead of recursing, so dropping a long chain doesn't overflow the
+    /// stack.
+    fn release(&self, s: CcPtr) {
+        self.releases.borrow_mut().push(s);
+        if self.releasing.replace(true) {
+            return;
+        }
+        {
+            let _guard = UnwindGuard(&self.releasing);
+            loop {
+                let next = self.releases.borrow_mut().pop();
+                match next {
+                    Some(s) => self.release_one(unsafe { s.as_ref() }),
+                    None => break,
+                }
+            }
+        }
+        self.releasing.set(false);
+        if !self.collecting.get() && self.requested.replace(false) {
+            self.collect_cycles();
+        }
+    }
+
+    /// Finalize and drop the value of a released box, then free it unless
+    /// it's still sitting in the roots buffer.
+    fn release_one(&self, obj: &dyn CcBoxPtr) {
+        debug_assert_eq!(obj.strong(), 0);
+        obj.metadata().set_color(Color::Black);
+        let buffered = obj.buffered();
+        // A buffered box is still in the roots buffer, so freeing it here
+        // would leave a dangling pointer there; it's left for `mark_roots`,
+        // which unbuffers and frees it. Either way, hold a weak reference so
+        // the memory stays put until we are done with it, whatever the
+        // value's `Drop` does with `Weak`s to it.
+        obj.inc_weak();
+        self.finalizers.run(obj.get_ptr());
+        unsafe {
+            obj.drop_value();
+            if !buffered {
+                free(obj.get_ptr());
             }
+            release_weak(obj.get_ptr());
+        }
+    }
+
+    /// Release a box whose only strong reference is going away after its
+    /// value was moved out, so there is nothing to drop. A finalizer
+    /// registered for it won't run.
+    pub(crate) fn release_moved(&self, s: CcPtr) {
+        let metadata = unsafe { s.as_ref() };
+        debug_assert_eq!(metadata.strong(), 1);
+        metadata.set_strong(0);
+        metadata.set_color(Color::Black);
+        self.finalizers.cancel(s);
+        if !metadata.buffered() {
+            unsafe { free(s) };
         }
     }
 
     fn possible_root(zelf: &dyn CcBoxPtr) {
-        if zelf.color() != Color::Purple {
-            zelf.metadata().color.set(Color::Purple);
+        // Green boxes can't be part of a cycle.
+        if zelf.color() != Color::Purple && zelf.color() != Color::Green {
+            zelf.metadata().set_color(Color::Purple);
             if !zelf.buffered() {
-                zelf.metadata().buffered.set(true);
-                zelf.metadata().root.add_root(zelf.get_ptr());
+                zelf.metadata().set_buffered(true);
+                zelf.metadata().root().add_root(zelf.get_ptr());
             }
         }
     }
@@ -98,32 +300,122 @@ impl CycleCollector {
         vec.push(box_ptr);
     }
 
-    pub fn collect_cycles(&self) {
-        #[cfg(test)]
-        println!("Call mark_roots() with roots: {:#?}", self);
-        self.mark_roots();
-        #[cfg(test)]
-        println!("Call scan_roots() with roots: {:#?}", self);
-        self.scan_roots();
-        #[cfg(test)]
-        println!("Call collect_roots() with roots: {:#?}", self);
-        self.collect_roots();
+    /// Take the strong reference of a `Cc` dropped by a `Trace` impl called
+    /// by the collector off later, returning whether it was deferred.
+    ///
+    /// The counts are in the middle of being recomputed at that point, so
+    /// the box might look released when it isn't. Until then the count stays
+    /// up, which keeps the box and everything it points to alive for this
+    /// collection.
+    pub(crate) fn defer_decrement(&self, s: &dyn CcBoxPtr) -> bool {
+        if !self.tracing.get() {
+            return false;
+        }
+        // The collection could still free the box, so keep the memory.
+        s.inc_weak();
+        self.deferred.borrow_mut().push(s.get_ptr());
+        true
+    }
+
+    /// Run `f`, which calls `Trace` impls, with `Cc` drops deferred.
+    pub(crate) fn tracing<R>(&self, f: impl FnOnce() -> R) -> R {
+        let tracing = self.tracing.replace(true);
+        let _guard = UnwindGuard(&self.tracing);
+        let result = f();
+        self.tracing.set(tracing);
+        result
+    }
+
+    /// Reclaim every garbage cycle reachable from the roots buffer, returning
+    /// what this collection did.
+    ///
+    /// Called while a collection or the release of a value is in progress,
+    /// e.g. from a `Drop` impl, this only asks for another pass once that is
+    /// done and returns empty stats.
+    pub fn collect_cycles(&self) -> CollectionStats {
+        if self.collecting.get() || self.releasing.get() {
+            self.requested.set(true);
+            return CollectionStats::default();
+        }
+        let mut stats = self.collect_once();
+        while self.requested.replace(false) {
+            stats += self.collect_once();
+        }
+        stats
+    }
+
+    fn collect_once(&self) -> CollectionStats {
+        let start = Instant::now();
+        let mut stats = CollectionStats {
+            collections: 1,
+            roots: self.roots.borrow().len(),
+            ..Default::default()
+        };
+        let _guard = self.begin_collection();
+        if self.checked.get() {
+            if let Err(err) = self.tracing(|| self.check_roots()) {
+                self.end_collection();
+                panic!("{}", err);
+            }
+        }
+        self.tracing(|| {
+            self.mark_roots(&mut stats);
+            self.scan_roots(&mut stats);
+        });
+        self.collect_roots(&mut stats);
+        self.untraceable.borrow_mut().clear();
+        self.end_collection();
+        if self.checked.get() {
+            if let Some(violation) = self.verify().first() {
+                panic!("heap left inconsistent by a collection: {:?}", violation);
+            }
+        }
+        stats.elapsed = start.elapsed();
+        self.trigger.collected(stats.roots, stats.freed);
+        let mut total = self.stats.get();
+        total += stats;
+        self.stats.set(total);
+        stats
+    }
+
+    /// Enter a collection, or anything else that must not run alongside one,
+    /// until `end_collection` or a panic.
+    pub(crate) fn begin_collection(&self) -> UnwindGuard<'_> {
+        self.collecting.set(true);
+        UnwindGuard(&self.collecting)
+    }
+
+    /// Take off the strong references deferred while tracing and leave the
+    /// collection.
+    pub(crate) fn end_collection(&self) {
+        let deferred = std::mem::take(&mut *self.deferred.borrow_mut());
+        for s in deferred {
+            let obj = unsafe { s.as_ref() };
+            if obj.strong() > 0 {
+                Self::decrement(obj);
+            }
+            unsafe { release_weak(s) };
+        }
+        self.collecting.set(false);
     }
 
-    fn mark_roots(&self) {
-        let mut new_roots: Vec<_> = self
-            .roots
-            .borrow_mut()
-            .drain(..)
+    fn mark_roots(&self, stats: &mut CollectionStats) {
+        // Taken out for the duration, like everywhere a `Trace` impl may run,
+        // in case it adds a root.
+        let roots = std::mem::take(&mut *self.roots.borrow_mut());
+        let mut new_roots: Vec<_> = roots
+            .into_iter()
             .filter(|s| {
                 // TODO: check if this is safe!
                 let s = unsafe { s.as_ref() };
                 if s.color() == Color::Purple {
-                    Self::mark_gray(s);
+                    self.mark_gray(s, stats);
                     true
                 } else {
-                    s.metadata().buffered.set(false);
+                    s.metadata().set_buffered(false);
                     if s.color() == Color::Black && s.strong() == 0 {
+                        stats.freed += 1;
+                        stats.bytes_freed += s.layout().size();
                         unsafe {
                             free(s.get_ptr());
                         }
@@ -135,67 +427,245 @@ impl CycleCollector {
         self.roots.borrow_mut().append(&mut new_roots);
     }
 
-    fn scan_roots(&self) {
-        for s in self.roots.borrow_mut().iter() {
+    fn scan_roots(&self, stats: &mut CollectionStats) {
+        let mut roots = std::mem::take(&mut *self.roots.borrow_mut());
+        for s in roots.iter() {
             // TODO: check if this is safe!
             let s = unsafe { s.as_ref() };
-            Self::scan(s)
+            self.scan(s, stats)
         }
+        roots.append(&mut self.roots.borrow_mut());
+        *self.roots.borrow_mut() = roots;
     }
 
-    fn collect_roots(&self) {
-        self.roots
-            .borrow_mut()
-            .drain(..)
-            .map(|s| {
+    /// Tear down every garbage cycle in two phases: first drop the values of
+    /// all white members, then free their memory. Finalizers run before
+    /// either, see `finalize`.
+    ///
+    /// `mark_gray` removed every edge out of the members from the strong
+    /// counts, so those are put back first for the dropped `Cc`s to remove
+    /// them exactly once. A member still counted once the values are gone is
+    /// only reachable through `Cc`s moved out of the cycle, which can't get
+    /// at its value anymore.
+    fn collect_roots(&self, stats: &mut CollectionStats) {
+        let roots = std::mem::take(&mut *self.roots.borrow_mut());
+        let mut white = Vec::new();
+        self.tracing(|| {
+            for s in roots {
                 // TODO: check if this is safe!
                 let s = unsafe { s.as_ref() };
-                s.metadata().buffered.set(false);
-                Self::collect_white(s);
-            })
-            .count();
+                s.metadata().set_buffered(false);
+                self.collect_white(s, &mut white, stats);
+            }
+            for s in &white {
+                unsafe { s.as_ref() }.trace(&mut |ch| {
+                    // Members of the garbage sit at zero, live objects never
+                    // do. Green ones were never taken off.
+                    if ch.strong() > 0 && ch.color() != Color::Green {
+                        ch.inc_strong();
+                    }
+                });
+            }
+        });
+        stats.white += white.len();
+        let finalize = white
+            .iter()
+            .any(|s| unsafe { s.as_ref() }.metadata().finalize());
+        if finalize && !self.finalize(&white) {
+            return;
+        }
+        // From here on the values of the members go away one by one, and a
+        // `Drop` impl could otherwise clone a `Cc` to one already dropped.
+        for s in &white {
+            unsafe { s.as_ref() }.metadata().set_torn_down();
+        }
+        // Count the edges between members again, plus one held by the
+        // teardown itself, so that a `Cc` a `Drop` impl moves out of its
+        // value instead of dropping it keeps the box from being freed.
+        self.tracing(|| {
+            for s in &white {
+                let s = unsafe { s.as_ref() };
+                s.inc_strong();
+                s.trace(&mut |ch| {
+                    // Boxes left over by an earlier teardown are Green.
+                    if ch.metadata().torn_down() && ch.color() != Color::Green {
+                        ch.inc_strong();
+                    }
+                });
+            }
+        });
+        for s in &white {
+            unsafe { s.as_ref().drop_value() };
+        }
+        for s in white {
+            let s = unsafe { s.as_ref() };
+            s.dec_strong();
+            if s.strong() > 0 {
+                // Moved out by a `Drop` impl. The box is freed once the last
+                // of those goes, see `decrement`; until then it is never
+                // traced again.
+                s.metadata().set_color(Color::Green);
+                continue;
+            }
+            if s.buffered() {
+                // Buffered again by a finalizer; `mark_roots` frees it.
+                s.metadata().set_color(Color::Black);
+                continue;
+            }
+            stats.freed += 1;
+            stats.bytes_freed += s.layout().size();
+            unsafe { free(s.get_ptr()) };
+        }
     }
 
-    fn mark_gray(zelf: &dyn CcBoxPtr) {
-        if zelf.color() != Color::Gray {
-            zelf.metadata().color.set(Color::Gray);
-            zelf.trace(&mut |ch| {
-                ch.dec_strong();
-                Self::mark_gray(ch);
-            });
+    /// Run the finalizers of the garbage in `white`, and return whether it is
+    /// still garbage afterwards.
+    ///
+    /// The members' counts are brought back to their real values for the
+    /// duration, plus one each so that nothing a finalizer does can release
+    /// them. Whatever is left over once those are taken off again comes from
+    /// outside: a finalizer resurrected the member. The garbage is then put
+    /// back together as live objects, and buffered so that a later
+    /// collection finds the part that is still garbage.
+    fn finalize(&self, white: &[CcPtr]) -> bool {
+        let members: HashSet<CcPtr> = white.iter().copied().collect();
+        let is_member = |ch: &CcBoxMetaData| members.contains(&ch.get_ptr());
+        let internal = |s: &CcPtr, f: &dyn Fn(&CcBoxMetaData)| {
+            self.tracing(|| {
+                unsafe { s.as_ref() }.trace(&mut |ch| {
+                    if is_member(ch) {
+                        f(ch)
+                    }
+                })
+            })
+        };
+        for s in white {
+            unsafe { s.as_ref() }.inc_strong();
+            internal(s, &|ch| ch.inc_strong());
+        }
+        for s in white {
+            self.finalizers.run(*s);
+        }
+        for s in white {
+            unsafe { s.as_ref() }.dec_strong();
+            internal(s, &|ch| ch.dec_strong());
+        }
+        if white.iter().all(|s| unsafe { s.as_ref() }.strong() == 0) {
+            return true;
         }
-    }
 
-    fn scan(zelf: &dyn CcBoxPtr) {
-        if zelf.color() == Color::Gray {
-            if zelf.strong() > 0 {
-                Self::scan_black(zelf);
+        for s in white {
+            internal(s, &|ch| ch.inc_strong());
+        }
+        let mut released = Vec::new();
+        for s in white {
+            let s = unsafe { s.as_ref() };
+            if s.strong() == 0 {
+                // A finalizer dropped the last reference to it.
+                released.push(s.get_ptr());
             } else {
-                zelf.metadata().color.set(Color::White);
-                zelf.trace(&mut |ch| {
-                    Self::scan(ch);
-                })
+                s.metadata().set_color(Color::Black);
+                Self::possible_root(s);
             }
         }
+        for s in released {
+            self.release(s);
+        }
+        false
     }
 
-    fn scan_black(zelf: &dyn CcBoxPtr) {
-        zelf.metadata().color.set(Color::Black);
-        zelf.trace(&mut |ch| {
-            ch.inc_strong();
-            if ch.color() != Color::Black {
-                Self::scan_black(ch);
-            }
-        })
+    fn mark_gray(&self, zelf: &dyn CcBoxPtr, stats: &mut CollectionStats) {
+        if zelf.color() != Color::Gray {
+            zelf.metadata().set_color(Color::Gray);
+            stats.marked_gray += 1;
+            self.run(Work::MarkGray(zelf.get_ptr()), &mut Vec::new(), stats);
+        }
     }
 
-    fn collect_white(zelf: &dyn CcBoxPtr) {
+    fn scan(&self, zelf: &dyn CcBoxPtr, stats: &mut CollectionStats) {
+        self.run(Work::Scan(zelf.get_ptr()), &mut Vec::new(), stats);
+    }
+
+    fn collect_white(
+        &self,
+        zelf: &dyn CcBoxPtr,
+        white: &mut Vec<CcPtr>,
+        stats: &mut CollectionStats,
+    ) {
         if zelf.color() == Color::White && !zelf.buffered() {
-            zelf.metadata().color.set(Color::Black);
-            zelf.trace(&mut |ch| Self::collect_white(ch));
-            unsafe {
-                free(zelf.get_ptr());
+            zelf.metadata().set_color(Color::Black);
+            self.run(Work::CollectWhite(zelf.get_ptr()), white, stats);
+        }
+    }
+
+    /// Process `work` and everything it pushes onto the work stack, pushing
+    /// the members of garbage cycles found by `CollectWhite` onto `white`.
+    fn run(&self, work: Work, white: &mut Vec<CcPtr>, stats: &mut CollectionStats) {
+        // Taken out for the duration so the tracers can push to it freely.
+        let mut stack = std::mem::take(&mut *self.work.borrow_mut());
+        stack.push(work);
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::MarkGray(s) => {
+                    let fully = traced_fully(|| {
+                        unsafe { s.as_ref() }.trace(&mut |ch| {
+                            if ch.color() == Color::Green {
+                                return;
+                            }
+                            ch.dec_strong();
+                            if ch.color() != Color::Gray {
+                                ch.metadata().set_color(Color::Gray);
+                                stats.marked_gray += 1;
+                                stack.push(Work::MarkGray(ch.get_ptr()));
+                            }
+                        })
+                    });
+                    // What it couldn't reach keeps the counts it was given by
+                    // the box, and so stays Black below. The box itself is
+                    // in use by whoever holds what it couldn't reach.
+                    if !fully {
+                        stats.untraceable += 1;
+                        self.untraceable.borrow_mut().insert(s);
+                    }
+                }
+                Work::Scan(s) => {
+                    let s = unsafe { s.as_ref() };
+                    if s.color() == Color::Gray {
+                        if s.strong() > 0 || self.untraceable.borrow().contains(&s.get_ptr()) {
+                            s.metadata().set_color(Color::Black);
+                            stack.push(Work::ScanBlack(s.get_ptr()));
+                        } else {
+                            s.metadata().set_color(Color::White);
+                            s.trace(&mut |ch| {
+                                if ch.color() != Color::Green {
+                                    stack.push(Work::Scan(ch.get_ptr()))
+                                }
+                            });
+                        }
+                    }
+                }
+                Work::ScanBlack(s) => unsafe { s.as_ref() }.trace(&mut |ch| {
+                    if ch.color() == Color::Green {
+                        return;
+                    }
+                    ch.inc_strong();
+                    if ch.color() != Color::Black {
+                        ch.metadata().set_color(Color::Black);
+                        stack.push(Work::ScanBlack(ch.get_ptr()));
+                    }
+                }),
+                Work::CollectWhite(s) => {
+                    white.push(s);
+                    unsafe { s.as_ref() }.trace(&mut |ch| {
+                        if ch.color() == Color::White && !ch.buffered() {
+                            ch.metadata().set_color(Color::Black);
+                            stack.push(Work::CollectWhite(ch.get_ptr()));
+                        }
+                    })
+                }
             }
         }
+        // Keep the allocation around for the next run.
+        *self.work.borrow_mut() = stack;
     }
 }
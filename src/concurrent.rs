@@ -0,0 +1,745 @@
+//! Concurrent variant of the collector, after Bacon & Rajan, "Concurrent
+//! Cycle Collection in Reference Counted Systems".
+//!
+//! Mutator threads never touch reference counts: cloning and dropping a
+//! [`Ccc`] only logs an increment or decrement into a per-thread buffer. A
+//! dedicated collector thread swaps those buffers out at every epoch boundary,
+//! applies the increments straight away and the decrements one epoch late, so
+//! a decrement can never overtake the increment it pairs with. Counts, colors
+//! and buffered flags are therefore only ever touched by the collector thread.
+//!
+//! Candidate cycles are found with the synchronous algorithm, except that
+//! trial deletion works on the cyclic reference count (CRC) and leaves the
+//! true count alone, as the graph may change under the collector's feet.
+//! Candidates are colored Orange and only freed one epoch later if they pass
+//! - the Δ-test: no member was incremented or decremented in between, so all
+//!   of them are still Orange, and
+//! - the Σ-test: every member's count is accounted for by edges from inside
+//!   the cycle, as computed at the end of the previous epoch.
+use std::{
+    cell::{Cell, RefCell, UnsafeCell},
+    fmt::Debug,
+    mem::ManuallyDrop,
+    ops::Deref,
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{Color, Trace, Tracer};
+
+/// Epoch length of [`ConcurrentCollector::new`].
+pub const DEFAULT_EPOCH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Boundaries [`ConcurrentCollector::collect`] waits for: one to swap out
+/// buffers that might have just missed an in-flight boundary, one to apply
+/// their decrements, and one to validate the candidate cycles those produce,
+/// plus the in-flight one itself.
+const EPOCHS_PER_COLLECT: u64 = 4;
+
+type NodePtr = NonNull<dyn Node>;
+
+/// A [`NodePtr`] on its way from a mutator to the collector thread.
+#[derive(Clone, Copy)]
+struct SendPtr(NodePtr);
+
+unsafe impl Send for SendPtr {}
+
+/// Type erased view of a [`CBox`] for the collector thread.
+trait Node {
+    fn header(&self) -> &Header;
+
+    /// Collect the boxes of the same collector this one owns into `out`.
+    fn children(&self, out: &mut Vec<NodePtr>);
+
+    /// Drop the value in place. Use [`drop_garbage`] rather than calling
+    /// this directly.
+    ///
+    /// # Safety
+    ///
+    /// Must be called exactly once, once the box is garbage.
+    unsafe fn drop_value(&self);
+}
+
+/// Collector state of a [`CBox`], only ever touched by the collector thread.
+struct Header {
+    rc: Cell<usize>,
+    crc: Cell<usize>,
+    color: Cell<Color>,
+    buffered: Cell<bool>,
+    shared: Arc<Shared>,
+}
+
+struct CBox<T: Trace> {
+    header: Header,
+    value: UnsafeCell<ManuallyDrop<T>>,
+}
+
+impl<T: Trace> CBox<T> {
+    #[inline(always)]
+    fn value(&self) -> &T {
+        unsafe { &*self.value.get() }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Node for CBox<T> {
+    fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn children(&self, out: &mut Vec<NodePtr>) {
+        EDGES.with(|edges| std::mem::swap(&mut *edges.borrow_mut(), out));
+        // `Ccc::trace` pushes to `EDGES` rather than calling the tracer, so
+        // that local collectors never see our boxes.
+        self.value().trace(&mut |_| {});
+        EDGES.with(|edges| std::mem::swap(&mut *edges.borrow_mut(), out));
+    }
+
+    unsafe fn drop_value(&self) {
+        ManuallyDrop::drop(&mut *self.value.get());
+    }
+}
+
+thread_local! {
+    /// The collector this thread runs, if it is a collector thread.
+    static COLLECTOR: Cell<*const Shared> = const { Cell::new(ptr::null()) };
+
+    /// Edges found by the `Node::children` call in progress.
+    static EDGES: RefCell<Vec<NodePtr>> = const { RefCell::new(Vec::new()) };
+
+    /// Whether the collector thread is inside [`drop_garbage`].
+    static DROPPING: Cell<bool> = const { Cell::new(false) };
+
+    /// Boxes whose `Ccc` was dropped by the [`drop_garbage`] call in progress.
+    static DROPPED: RefCell<Vec<NodePtr>> = const { RefCell::new(Vec::new()) };
+
+    /// This thread's mutation buffer for each collector it has used.
+    static LOGS: RefCell<Vec<(usize, Arc<Mutex<Log>>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether the current thread is the collector thread of `shared`.
+fn is_collector_of(shared: &Arc<Shared>) -> bool {
+    COLLECTOR.with(|c| c.get() == Arc::as_ptr(shared))
+}
+
+/// Drop the value of the garbage box `s` and return the boxes whose `Ccc`s
+/// went with it, for the collector to decrement right away.
+///
+/// Meanwhile, the collector's own `Ccc`s bypass the logs: a drop is reported
+/// back here rather than logged, and a clone is applied on the spot, so that
+/// a `Drop` impl cloning and dropping a `Ccc` cancels out. Dropping an edge of
+/// the value thus never overtakes the increment it pairs with.
+///
+/// # Safety
+///
+/// Must be called on the collector thread, exactly once, once `s` is garbage.
+unsafe fn drop_garbage(s: NodePtr) -> Vec<NodePtr> {
+    let dropping = DROPPING.replace(true);
+    s.as_ref().drop_value();
+    DROPPING.set(dropping);
+    DROPPED.take()
+}
+
+/// Whether the current thread is the collector thread of `shared` and inside
+/// [`drop_garbage`].
+fn is_dropping_for(shared: &Arc<Shared>) -> bool {
+    DROPPING.get() && is_collector_of(shared)
+}
+
+/// Reference count updates logged by one mutator thread during an epoch.
+#[derive(Default)]
+struct Log {
+    incs: Vec<SendPtr>,
+    decs: Vec<SendPtr>,
+}
+
+// Only ever moved onto the collector thread before any node was buffered.
+unsafe impl Send for Collector {}
+
+impl Log {
+    fn append(&mut self, other: &mut Log) {
+        self.incs.append(&mut other.incs);
+        self.decs.append(&mut other.decs);
+    }
+}
+
+struct EpochState {
+    /// Boundaries completed so far.
+    epoch: u64,
+    /// Epoch some `collect` call is waiting for.
+    requested: u64,
+    shutdown: bool,
+}
+
+/// State shared between mutators and the collector thread.
+struct Shared {
+    id: usize,
+    interval: Duration,
+    state: Mutex<EpochState>,
+    /// Signalled on collection requests, shutdown and finished boundaries.
+    cond: Condvar,
+    logs: Mutex<Vec<Arc<Mutex<Log>>>>,
+    /// Updates from threads whose thread locals are already gone.
+    orphans: Mutex<Log>,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn log(self: &Arc<Self>, f: impl FnOnce(&mut Log)) {
+        if self.stopped.load(Ordering::Acquire) {
+            // Nobody is left to apply the update, so the box just leaks.
+            return;
+        }
+        let mut f = Some(f);
+        let logged = LOGS.try_with(|logs| {
+            let mut logs = logs.borrow_mut();
+            let log = match logs.iter().find(|(id, _)| *id == self.id) {
+                Some((_, log)) => log,
+                None => {
+                    let log = Arc::new(Mutex::new(Log::default()));
+                    self.logs.lock().unwrap().push(log.clone());
+                    logs.push((self.id, log));
+                    &logs.last().unwrap().1
+                }
+            };
+            (f.take().unwrap())(&mut log.lock().unwrap());
+        });
+        if logged.is_err() {
+            (f.take().unwrap())(&mut self.orphans.lock().unwrap());
+        }
+    }
+
+    /// Swap out every mutator's buffer, ending the epoch.
+    fn take_logs(&self) -> Log {
+        let mut taken = std::mem::take(&mut *self.orphans.lock().unwrap());
+        self.logs.lock().unwrap().retain(|log| {
+            taken.append(&mut log.lock().unwrap());
+            // Only our reference is left once the thread has exited.
+            Arc::strong_count(log) > 1
+        });
+        taken
+    }
+}
+
+/// Collector thread state.
+struct Collector {
+    shared: Arc<Shared>,
+    roots: Vec<NodePtr>,
+    /// Candidate cycles awaiting the Σ- and Δ-tests.
+    cycles: Vec<Vec<NodePtr>>,
+    /// Decrements of the previous epoch.
+    decs: Vec<SendPtr>,
+}
+
+/// A step of a collection phase, see `Collector::run_phase`.
+enum Work {
+    /// The node is already Gray; take its edges off its children's CRC and
+    /// gray them.
+    MarkGray(NodePtr),
+    Scan(NodePtr),
+    /// The node is already Black; blacken its children.
+    ScanBlack(NodePtr),
+    CollectWhite(NodePtr),
+}
+
+#[inline(always)]
+fn header<'a>(node: NodePtr) -> &'a Header {
+    unsafe { node.as_ref().header() }
+}
+
+fn children(node: NodePtr) -> Vec<NodePtr> {
+    let mut out = Vec::new();
+    unsafe { node.as_ref().children(&mut out) };
+    out
+}
+
+impl Collector {
+    fn run(mut self) {
+        COLLECTOR.with(|c| c.set(Arc::as_ptr(&self.shared)));
+        let shared = self.shared.clone();
+        let mut state = shared.state.lock().unwrap();
+        while !state.shutdown {
+            if state.requested <= state.epoch {
+                state = shared.cond.wait_timeout(state, shared.interval).unwrap().0;
+                if state.shutdown {
+                    break;
+                }
+            }
+            drop(state);
+            self.boundary();
+            state = shared.state.lock().unwrap();
+            state.epoch += 1;
+            shared.cond.notify_all();
+        }
+        drop(state);
+        // Flush whatever the mutators logged before the collector went away.
+        for _ in 0..EPOCHS_PER_COLLECT {
+            self.boundary();
+        }
+        shared.stopped.store(true, Ordering::Release);
+        shared.cond.notify_all();
+    }
+
+    /// End the current epoch: apply the buffered updates, then free the
+    /// cycles that survived validation and look for new candidates.
+    fn boundary(&mut self) {
+        let log = self.shared.take_logs();
+        for SendPtr(s) in log.incs {
+            Self::increment(s);
+        }
+        for SendPtr(s) in std::mem::replace(&mut self.decs, log.decs) {
+            self.decrement(s);
+        }
+        self.free_cycles();
+        self.collect_cycles();
+        self.sigma_preparation();
+    }
+
+    fn increment(s: NodePtr) {
+        let h = header(s);
+        h.rc.set(h.rc.get() + 1);
+        h.color.set(Color::Black);
+    }
+
+    fn decrement(&mut self, s: NodePtr) {
+        let h = header(s);
+        debug_assert!(h.rc.get() > 0);
+        h.rc.set(h.rc.get() - 1);
+        if h.rc.get() == 0 {
+            self.release(s);
+        } else {
+            self.possible_root(s);
+        }
+    }
+
+    fn release(&mut self, s: NodePtr) {
+        let h = header(s);
+        h.color.set(Color::Black);
+        for t in unsafe { drop_garbage(s) } {
+            self.decrement(t);
+        }
+        if !h.buffered.get() {
+            unsafe { deallocate(s) };
+        }
+    }
+
+    fn possible_root(&mut self, s: NodePtr) {
+        let h = header(s);
+        if h.color.get() != Color::Purple {
+            h.color.set(Color::Purple);
+            if !h.buffered.get() {
+                h.buffered.set(true);
+                self.roots.push(s);
+            }
+        }
+    }
+
+    fn collect_cycles(&mut self) {
+        self.mark_roots();
+        self.scan_roots();
+        self.collect_roots();
+    }
+
+    fn mark_roots(&mut self) {
+        for s in std::mem::take(&mut self.roots) {
+            let h = header(s);
+            if h.color.get() == Color::Purple && h.rc.get() > 0 {
+                Self::mark_gray(s);
+                self.roots.push(s);
+            } else {
+                h.buffered.set(false);
+                if h.rc.get() == 0 {
+                    if h.color.get() == Color::Black {
+                        // Released while buffered.
+                        unsafe { deallocate(s) };
+                    } else {
+                        // Dropped to zero by `cyclic_decrement`.
+                        self.release(s);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Trial deletion on the CRC: the true count must survive the graph
+    /// changing between here and `scan`.
+    fn mark_gray(s: NodePtr) {
+        let h = header(s);
+        h.color.set(Color::Gray);
+        h.crc.set(h.rc.get());
+        Self::run_phase(Work::MarkGray(s), &mut Vec::new());
+    }
+
+    fn scan_roots(&mut self) {
+        for &s in &self.roots {
+            Self::run_phase(Work::Scan(s), &mut Vec::new());
+        }
+    }
+
+    fn collect_roots(&mut self) {
+        for s in std::mem::take(&mut self.roots) {
+            let h = header(s);
+            if h.color.get() == Color::White {
+                let mut cycle = Vec::new();
+                Self::run_phase(Work::CollectWhite(s), &mut cycle);
+                self.cycles.push(cycle);
+            } else if h.color.get() == Color::Purple {
+                // Became a possible root after `mark_roots`, e.g. through
+                // `release`, so it hasn't been looked at yet.
+                self.roots.push(s);
+            } else if h.color.get() != Color::Orange {
+                // Orange roots were picked up by an earlier cycle and stay
+                // buffered in the cycle buffer.
+                h.buffered.set(false);
+            }
+        }
+    }
+
+    /// Process `work` and everything it pushes onto the work stack, pushing
+    /// the members of the candidate cycle found by `CollectWhite` onto
+    /// `cycle`. Long chains would overflow the collector thread's stack if
+    /// the phases recursed.
+    fn run_phase(work: Work, cycle: &mut Vec<NodePtr>) {
+        let mut stack = vec![work];
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::MarkGray(s) => {
+                    for t in children(s) {
+                        let ht = header(t);
+                        if ht.color.get() != Color::Gray {
+                            ht.color.set(Color::Gray);
+                            ht.crc.set(ht.rc.get());
+                            stack.push(Work::MarkGray(t));
+                        }
+                        // Saturate: the increment for an edge a mutator just
+                        // stored may not have been applied yet.
+                        ht.crc.set(ht.crc.get().saturating_sub(1));
+                    }
+                }
+                Work::Scan(s) => {
+                    let h = header(s);
+                    if h.color.get() == Color::Gray {
+                        if h.crc.get() > 0 {
+                            h.color.set(Color::Black);
+                            stack.push(Work::ScanBlack(s));
+                        } else {
+                            h.color.set(Color::White);
+                            stack.extend(children(s).into_iter().map(Work::Scan));
+                        }
+                    }
+                }
+                Work::ScanBlack(s) => {
+                    for t in children(s) {
+                        let ht = header(t);
+                        if ht.color.get() != Color::Black {
+                            ht.color.set(Color::Black);
+                            stack.push(Work::ScanBlack(t));
+                        }
+                    }
+                }
+                Work::CollectWhite(s) => {
+                    let h = header(s);
+                    if h.color.get() == Color::White {
+                        h.color.set(Color::Orange);
+                        h.buffered.set(true);
+                        cycle.push(s);
+                        stack.extend(children(s).into_iter().map(Work::CollectWhite));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compute the external count of every candidate cycle into the CRC of
+    /// its members, for the Σ-test at the next boundary.
+    fn sigma_preparation(&mut self) {
+        for cycle in &self.cycles {
+            for &n in cycle {
+                let h = header(n);
+                h.color.set(Color::Red);
+                h.crc.set(h.rc.get());
+            }
+            for &n in cycle {
+                for m in children(n) {
+                    let hm = header(m);
+                    if hm.color.get() == Color::Red && hm.crc.get() > 0 {
+                        hm.crc.set(hm.crc.get() - 1);
+                    }
+                }
+            }
+            for &n in cycle {
+                header(n).color.set(Color::Orange);
+            }
+        }
+    }
+
+    fn free_cycles(&mut self) {
+        // Later cycles may point into earlier ones but not the other way
+        // around, so free them first to let the earlier ones pass Σ.
+        for cycle in std::mem::take(&mut self.cycles).into_iter().rev() {
+            if Self::delta_test(&cycle) && Self::sigma_test(&cycle) {
+                self.free_cycle(cycle);
+            } else {
+                self.refurbish(cycle);
+            }
+        }
+    }
+
+    fn delta_test(cycle: &[NodePtr]) -> bool {
+        cycle
+            .iter()
+            .all(|&n| header(n).color.get() == Color::Orange)
+    }
+
+    fn sigma_test(cycle: &[NodePtr]) -> bool {
+        cycle.iter().map(|&n| header(n).crc.get()).sum::<usize>() == 0
+    }
+
+    /// Put the members of a cycle that failed validation back into normal
+    /// circulation, keeping one of them as a root so it gets looked at again.
+    fn refurbish(&mut self, cycle: Vec<NodePtr>) {
+        for (i, n) in cycle.into_iter().enumerate() {
+            let h = header(n);
+            let color = h.color.get();
+            if h.rc.get() == 0 && color == Color::Black {
+                // Released while waiting in the cycle buffer.
+                h.buffered.set(false);
+                unsafe { deallocate(n) };
+            } else if h.rc.get() == 0
+                || color == Color::Purple
+                || (i == 0 && color == Color::Orange)
+            {
+                h.color.set(Color::Purple);
+                self.roots.push(n);
+            } else {
+                h.color.set(Color::Black);
+                h.buffered.set(false);
+            }
+        }
+    }
+
+    fn free_cycle(&mut self, cycle: Vec<NodePtr>) {
+        for &n in &cycle {
+            header(n).color.set(Color::Red);
+        }
+        let kids: Vec<_> = cycle
+            .iter()
+            .flat_map(|&n| unsafe { drop_garbage(n) })
+            .collect();
+        for m in kids {
+            self.cyclic_decrement(m);
+        }
+        for n in cycle {
+            unsafe { deallocate(n) };
+        }
+    }
+
+    fn cyclic_decrement(&mut self, m: NodePtr) {
+        let h = header(m);
+        match h.color.get() {
+            Color::Red => {}
+            Color::Orange => {
+                // A member of another candidate cycle.
+                h.rc.set(h.rc.get() - 1);
+                h.crc.set(h.crc.get().saturating_sub(1));
+            }
+            _ => self.decrement(m),
+        }
+    }
+}
+
+/// Give back the memory of a box whose value has already been dropped.
+unsafe fn deallocate(s: NodePtr) {
+    // `CBox` keeps its value in a `ManuallyDrop`, so this only drops the
+    // header.
+    drop(Box::from_raw(s.as_ptr()));
+}
+
+/// Owner of the collector thread all [`Ccc`]s created from it report to.
+///
+/// Dropping it stops the thread after applying everything logged so far;
+/// objects that are still alive then are leaked.
+pub struct ConcurrentCollector {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Debug for ConcurrentCollector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConcurrentCollector")
+            .field("epoch", &self.epoch())
+            .field("interval", &self.shared.interval)
+            .finish()
+    }
+}
+
+impl Default for ConcurrentCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConcurrentCollector {
+    pub fn new() -> Self {
+        Self::with_epoch_interval(DEFAULT_EPOCH_INTERVAL)
+    }
+
+    /// Spawn a collector thread that ends an epoch every `interval`.
+    pub fn with_epoch_interval(interval: Duration) -> Self {
+        static IDS: AtomicUsize = AtomicUsize::new(0);
+        let shared = Arc::new(Shared {
+            id: IDS.fetch_add(1, Ordering::Relaxed),
+            interval,
+            state: Mutex::new(EpochState {
+                epoch: 0,
+                requested: 0,
+                shutdown: false,
+            }),
+            cond: Condvar::new(),
+            logs: Mutex::new(Vec::new()),
+            orphans: Mutex::new(Log::default()),
+            stopped: AtomicBool::new(false),
+        });
+        let collector = Collector {
+            shared: shared.clone(),
+            roots: Vec::new(),
+            cycles: Vec::new(),
+            decs: Vec::new(),
+        };
+        let thread = thread::Builder::new()
+            .name("cc_bacon collector".into())
+            .spawn(move || collector.run())
+            .expect("failed to spawn the collector thread");
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Number of epoch boundaries the collector thread has completed.
+    pub fn epoch(&self) -> u64 {
+        self.shared.state.lock().unwrap().epoch
+    }
+
+    /// Block until every update logged before this call has been applied and
+    /// the garbage cycles it produced have been freed.
+    ///
+    /// Does nothing when called from the collector thread itself, e.g. from a
+    /// `Drop` impl of a collected value.
+    pub fn collect(&self) {
+        if is_collector_of(&self.shared) {
+            return;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        let target = state.epoch + EPOCHS_PER_COLLECT;
+        state.requested = state.requested.max(target);
+        self.shared.cond.notify_all();
+        while state.epoch < target && !state.shutdown {
+            state = self.shared.cond.wait(state).unwrap();
+        }
+    }
+}
+
+impl Drop for ConcurrentCollector {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.cond.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // Don't turn a panic in a collected value's `Drop` into a double
+            // panic here.
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A reference-counted pointer that can be shared between threads, collected
+/// by a [`ConcurrentCollector`].
+pub struct Ccc<T: Trace + Send + Sync + 'static> {
+    _ptr: NonNull<CBox<T>>,
+}
+
+unsafe impl<T: Trace + Send + Sync + 'static> Send for Ccc<T> {}
+unsafe impl<T: Trace + Send + Sync + 'static> Sync for Ccc<T> {}
+
+impl<T: Trace + Send + Sync + 'static> Ccc<T> {
+    pub fn new(value: T, collector: &ConcurrentCollector) -> Ccc<T> {
+        let boxed = Box::new(CBox {
+            header: Header {
+                rc: 1.into(),
+                crc: 0.into(),
+                color: Color::Black.into(),
+                buffered: false.into(),
+                shared: collector.shared.clone(),
+            },
+            value: UnsafeCell::new(ManuallyDrop::new(value)),
+        });
+        unsafe {
+            Ccc {
+                _ptr: NonNull::new_unchecked(Box::into_raw(boxed)),
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &CBox<T> {
+        unsafe { self._ptr.as_ref() }
+    }
+
+    fn node(&self) -> SendPtr {
+        SendPtr(self._ptr as NodePtr)
+    }
+}
+
+unsafe impl<T: Trace + Send + Sync + 'static> Trace for Ccc<T> {
+    fn trace(&self, _tracer: &mut Tracer) {
+        // Edges between `Ccc`s are only visible to their own collector
+        // thread; local collectors must not touch their counts.
+        if is_collector_of(&self.inner().header.shared) {
+            EDGES.with(|edges| edges.borrow_mut().push(self.node().0));
+        }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Deref for Ccc<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        self.inner().value()
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Clone for Ccc<T> {
+    fn clone(&self) -> Self {
+        let node = self.node();
+        let shared = &self.inner().header.shared;
+        if is_dropping_for(shared) {
+            // Cloned from a garbage value's `Drop`, see `drop_garbage`. A
+            // member of the cycle being freed stays Red.
+            let h = header(node.0);
+            h.rc.set(h.rc.get() + 1);
+            if h.color.get() != Color::Red {
+                h.color.set(Color::Black);
+            }
+        } else {
+            shared.log(|log| log.incs.push(node));
+        }
+        Ccc { _ptr: self._ptr }
+    }
+}
+
+impl<T: Trace + Send + Sync + 'static> Drop for Ccc<T> {
+    fn drop(&mut self) {
+        let shared = &self.inner().header.shared;
+        let node = self.node();
+        if is_dropping_for(shared) {
+            DROPPED.with(|dropped| dropped.borrow_mut().push(node.0));
+        } else {
+            shared.log(|log| log.decs.push(node));
+        }
+    }
+}
@@ -1,28 +1,34 @@
-use std::{
-    alloc::{dealloc, Layout},
-    ptr::NonNull,
-};
+use std::alloc::dealloc;
 
 use crate::{CcBoxPtr, CcPtr};
 
-pub unsafe fn deallocate(ptr: NonNull<dyn CcBoxPtr>) {
-    #[cfg(test)]
-    println!("Actually deallocate in here for {:?}", ptr);
-    dealloc(ptr.cast().as_ptr(), Layout::for_value(ptr.as_ref()));
+/// Give the allocation back, dropping the metadata but not the value, which
+/// must already have been dropped through [`CcBoxPtr::drop_value`].
+pub unsafe fn deallocate(ptr: CcPtr) {
+    let metadata = ptr.as_ref();
+    let root = metadata.root.get();
+    // The metadata has nothing to drop, and the value is already gone.
+    dealloc(ptr.as_ptr().cast(), metadata.layout());
+    if let Some(root) = root {
+        root.live.set(root.live.get() - 1);
+    }
+}
+
+/// Drop one weak reference, deallocating the box once none remain.
+pub unsafe fn release_weak(s: CcPtr) {
+    s.as_ref().dec_weak();
+
+    if s.as_ref().weak() == 0 {
+        deallocate(s);
+    }
 }
 
 /// Deallocate the box if possible. `s` should already have been dropped.
 pub unsafe fn free(s: CcPtr) {
-    #[cfg(test)]
-    println!("Called free in here for {:?}", s);
     debug_assert_eq!(s.as_ref().strong(), 0);
     debug_assert!(!s.as_ref().buffered());
 
     // Remove the implicit "strong weak" pointer now that we've destroyed
     // the contents.
-    s.as_ref().dec_weak();
-
-    if s.as_ref().weak() == 0 {
-        deallocate(s);
-    }
+    release_weak(s);
 }
@@ -0,0 +1,84 @@
+//! Dump the object graph as [Graphviz DOT](https://graphviz.org/doc/info/lang.html)
+//! for debugging leaks.
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::{CcBoxPtr, CcPtr, CycleCollector, Trace};
+
+impl CycleCollector {
+    /// Write the graph of every `Cc` reachable from the buffered possible
+    /// roots and from `from` to `out` as a DOT digraph.
+    ///
+    /// Each node is labelled with the type of its value, its strong and weak
+    /// counts and its `Color`; buffered roots are drawn with a double border.
+    /// Each edge is one `Cc` reported by the parent's `Trace`.
+    ///
+    /// Boxes whose value has already been dropped are shown without edges.
+    ///
+    /// Fails in the middle of a collection or release, whose counts and
+    /// colors are in flux.
+    pub fn write_dot<W: Write>(&self, out: &mut W, from: &[&dyn CcBoxPtr]) -> io::Result<()> {
+        if self.collecting.get() || self.releasing.get() {
+            return Err(io::Error::other(
+                "can't write the graph during a collection or release",
+            ));
+        }
+        let _guard = self.begin_collection();
+        let written = self.tracing(|| self.write_graph(out, from));
+        self.end_collection();
+        written
+    }
+
+    fn write_graph<W: Write>(&self, out: &mut W, from: &[&dyn CcBoxPtr]) -> io::Result<()> {
+        let mut ids: HashMap<CcPtr, usize> = HashMap::new();
+        let mut stack: Vec<CcPtr> = Vec::new();
+        let mut visit = |s: CcPtr, stack: &mut Vec<CcPtr>| {
+            let next = ids.len();
+            *ids.entry(s).or_insert_with(|| {
+                stack.push(s);
+                next
+            })
+        };
+
+        for s in self.roots.borrow().iter() {
+            visit(*s, &mut stack);
+        }
+        for s in from {
+            visit(s.get_ptr(), &mut stack);
+        }
+
+        writeln!(out, "digraph heap {{")?;
+        while let Some(s) = stack.pop() {
+            let s = unsafe { s.as_ref() };
+            let id = visit(s.get_ptr(), &mut stack);
+            let metadata = s.metadata();
+            writeln!(
+                out,
+                "    n{} [label=\"{}\\nstrong {}, weak {}\\n{:?}\"{}];",
+                id,
+                escape(s.type_name()),
+                metadata.strong(),
+                metadata.weak(),
+                metadata.color(),
+                if metadata.buffered() {
+                    ", peripheries=2"
+                } else {
+                    ""
+                },
+            )?;
+            if s.strong() == 0 {
+                continue;
+            }
+            let mut children = Vec::new();
+            s.trace(&mut |ch| children.push(ch.get_ptr()));
+            for ch in children {
+                writeln!(out, "    n{} -> n{};", id, visit(ch, &mut stack))?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
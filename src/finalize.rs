@@ -0,0 +1,71 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ptr::NonNull;
+
+use crate::{CcBox, CcBoxMetaData, CcPtr, Trace};
+
+/// Code to run on a value shortly before it is dropped, like Python's
+/// `__del__`.
+///
+/// Attach it with [`Cc::new_finalizable`](crate::Cc::new_finalizable). It runs
+/// at most once per box: when the strong count reaches zero, or, for a member
+/// of a garbage cycle, before any value of the cycle is dropped, so every
+/// `Cc` in the cycle can still be dereferenced.
+///
+/// A finalizer may store a clone of a `Cc` in the cycle somewhere live. The
+/// collector notices this resurrection and leaves the whole garbage found by
+/// that collection alone; it is reexamined, without running the finalizers
+/// again, by a later collection.
+///
+/// A finalizer may call `collect_cycles`, which, as from a `Drop` impl, only
+/// asks for another pass once the current one is done.
+pub trait Finalize {
+    fn finalize(&self);
+}
+
+type FinalizeFn = unsafe fn(&CcBoxMetaData);
+
+unsafe fn finalize_box<T: Finalize + Trace + 'static>(s: &CcBoxMetaData) {
+    CcBox::<T>::from_metadata(s).value().finalize()
+}
+
+/// The finalizers of a collector's boxes that haven't run yet, by address.
+///
+/// Only boxes with their `finalize` flag set have an entry, so the others pay
+/// nothing but a bit check.
+#[derive(Debug, Default)]
+pub(crate) struct Finalizers {
+    pending: RefCell<HashMap<CcPtr, FinalizeFn>>,
+}
+
+impl Finalizers {
+    pub fn register<T: Finalize + Trace + 'static>(&self, s: &CcBox<T>) {
+        s.metadata.set_finalize(true);
+        self.pending
+            .borrow_mut()
+            .insert(NonNull::from(&s.metadata), finalize_box::<T>);
+    }
+
+    /// Forget the finalizer of `s` without running it.
+    pub fn cancel(&self, s: CcPtr) {
+        let metadata = unsafe { s.as_ref() };
+        if metadata.finalize() {
+            metadata.set_finalize(false);
+            self.pending.borrow_mut().remove(&s);
+        }
+    }
+
+    /// Run the finalizer of `s`, if it has one that hasn't run yet.
+    pub fn run(&self, s: CcPtr) {
+        let metadata = unsafe { s.as_ref() };
+        if metadata.finalize() {
+            metadata.set_finalize(false);
+            let finalize = self
+                .pending
+                .borrow_mut()
+                .remove(&s)
+                .expect("finalizer registered");
+            unsafe { finalize(metadata) }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! The metadata at the start of every `CcBox`: a pointer to the box's
+//! [`VTable`] followed by four `u32`s.
+//!
+//! With the vtable in the header, a pointer to the header is all the
+//! collector needs to handle a box, so roots and tracers pass thin pointers.
+//!
+//! The color, the buffered, finalize and torn down flags and the strong count
+//! share one word:
+//!
+//! ```text
+//!  31                      6     5           4          3          2     0
+//! +------------------------+-----------+----------+----------+--------+
+//! |         strong         | torn down | finalize | buffered | color  |
+//! +------------------------+-----------+----------+----------+--------+
+//! ```
+//!
+//! A strong count that doesn't fit in its 26 bits is kept in the collector's
+//! overflow table instead, with the field left at [`STRONG_OVERFLOW`].
+use std::alloc::Layout;
+use std::any::TypeId;
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::ptr::NonNull;
+
+use crate::collect::RootsRef;
+use crate::registry::CollectorId;
+use crate::{CcBoxPtr, CcPtr, Color, CycleCollector, Trace, Tracer};
+
+const COLOR_MASK: u32 = 0b111;
+const BUFFERED: u32 = 1 << 3;
+const FINALIZE: u32 = 1 << 4;
+const TORN_DOWN: u32 = 1 << 5;
+const STRONG_SHIFT: u32 = 6;
+
+/// Value of the strong field when the count lives in the overflow table.
+pub(crate) const STRONG_OVERFLOW: u32 = u32::MAX >> STRONG_SHIFT;
+
+/// How to handle the value of a box whose type is only known through its
+/// header.
+pub(crate) struct VTable {
+    pub trace: unsafe fn(&CcBoxMetaData, &mut Tracer),
+    pub drop_value: unsafe fn(&CcBoxMetaData),
+    /// Layout of the whole box.
+    pub layout: unsafe fn(&CcBoxMetaData) -> Layout,
+    pub type_name: fn() -> &'static str,
+    /// Type of the value the box was allocated with, for downcasting.
+    pub type_id: fn() -> TypeId,
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct CcBoxMetaData {
+    pub(crate) vtable: &'static VTable,
+    state: Cell<u32>,
+    weak: Cell<u32>,
+    pub(crate) root: CollectorId,
+    /// Number of elements of a slice or `str` value, unused for sized ones.
+    pub(crate) len: u32,
+}
+
+impl Debug for CcBoxMetaData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metadata")
+            .field("strong", &self.strong())
+            .field("weak", &self.weak())
+            .field("buffered", &self.buffered())
+            .field("finalize", &self.finalize())
+            .field("torn_down", &self.torn_down())
+            .field("color", &self.color())
+            .finish()
+    }
+}
+
+impl CcBoxMetaData {
+    /*
+    There is an implicit weak pointer owned by all the strong
+    pointers, which ensures that the weak destructor never frees
+    the allocation while the strong destructor is running, even
+    if the weak pointer is stored inside the strong one.
+    */
+    pub(crate) fn with(root: CollectorId, vtable: &'static VTable) -> Self {
+        Self {
+            vtable,
+            state: Cell::new(1 << STRONG_SHIFT | Color::Black as u32),
+            weak: Cell::new(1),
+            root,
+            len: 0,
+        }
+    }
+
+    /// The metadata of a box about to be allocated for `roots`, which may
+    /// collect first.
+    pub(crate) fn for_new_box(roots: &RootsRef, vtable: &'static VTable, size: usize) -> Self {
+        roots.maybe_collect();
+        roots.note_allocation(size);
+        let metadata = Self::with(CollectorId::of(roots), vtable);
+        roots.live.set(roots.live.get() + 1);
+        metadata
+    }
+
+    #[inline]
+    pub(crate) fn layout(&self) -> Layout {
+        unsafe { (self.vtable.layout)(self) }
+    }
+
+    /// The collector this box belongs to.
+    #[inline]
+    pub(crate) fn root(&self) -> &CycleCollector {
+        unsafe { self.root.get() }.expect("no collector on an exiting thread")
+    }
+
+    #[inline]
+    fn set_bits(&self, mask: u32, bits: u32) {
+        self.state.set(self.state.get() & !mask | bits);
+    }
+
+    #[inline]
+    pub(crate) fn color(&self) -> Color {
+        Color::ALL[(self.state.get() & COLOR_MASK) as usize]
+    }
+
+    #[inline]
+    pub(crate) fn set_color(&self, color: Color) {
+        self.set_bits(COLOR_MASK, color as u32);
+    }
+
+    #[inline]
+    pub(crate) fn buffered(&self) -> bool {
+        self.state.get() & BUFFERED != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_buffered(&self, buffered: bool) {
+        self.set_bits(BUFFERED, if buffered { BUFFERED } else { 0 });
+    }
+
+    /// Whether a finalizer registered with the collector still has to run.
+    #[inline]
+    pub(crate) fn finalize(&self) -> bool {
+        self.state.get() & FINALIZE != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_finalize(&self, finalize: bool) {
+        self.set_bits(FINALIZE, if finalize { FINALIZE } else { 0 });
+    }
+
+    /// Whether the box is a member of a garbage cycle whose values are being
+    /// or were dropped, which must not get strong references again nor have
+    /// its value reached through the ones moved out of the cycle.
+    #[inline]
+    pub(crate) fn torn_down(&self) -> bool {
+        self.state.get() & TORN_DOWN != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_torn_down(&self) {
+        self.set_bits(TORN_DOWN, TORN_DOWN);
+    }
+
+    #[inline]
+    pub(crate) fn strong(&self) -> usize {
+        match self.state.get() >> STRONG_SHIFT {
+            STRONG_OVERFLOW => self.root().overflow.borrow()[&(self as *const _)],
+            strong => strong as usize,
+        }
+    }
+
+    pub(crate) fn set_strong(&self, strong: usize) {
+        let overflowed = self.state.get() >> STRONG_SHIFT == STRONG_OVERFLOW;
+        let field = match u32::try_from(strong) {
+            Ok(strong) if strong < STRONG_OVERFLOW => {
+                if overflowed {
+                    self.root()
+                        .overflow
+                        .borrow_mut()
+                        .remove(&(self as *const _));
+                }
+                strong
+            }
+            _ => {
+                self.root()
+                    .overflow
+                    .borrow_mut()
+                    .insert(self as *const _, strong);
+                STRONG_OVERFLOW
+            }
+        };
+        self.set_bits(
+            !(COLOR_MASK | BUFFERED | FINALIZE | TORN_DOWN),
+            field << STRONG_SHIFT,
+        );
+    }
+
+    #[inline]
+    pub(crate) fn weak(&self) -> usize {
+        self.weak.get() as usize
+    }
+
+    #[inline]
+    pub(crate) fn set_weak(&self, weak: usize) {
+        self.weak
+            .set(u32::try_from(weak).expect("weak count overflow"));
+    }
+}
+
+unsafe impl Trace for CcBoxMetaData {
+    fn trace(&self, tracer: &mut Tracer) {
+        unsafe { (self.vtable.trace)(self, tracer) }
+    }
+}
+
+impl CcBoxPtr for CcBoxMetaData {
+    #[inline(always)]
+    fn metadata(&self) -> &CcBoxMetaData {
+        self
+    }
+
+    fn get_ptr(&self) -> CcPtr {
+        NonNull::from(self)
+    }
+
+    unsafe fn drop_value(&self) {
+        (self.vtable.drop_value)(self)
+    }
+
+    fn type_name(&self) -> &'static str {
+        (self.vtable.type_name)()
+    }
+}
@@ -1,17 +1,56 @@
+// Lets `#[derive(Trace)]` output, which names `::cc_bacon`, work in here too.
+extern crate self as cc_bacon;
+
+mod any;
+mod atomic;
 mod box_ptr;
+mod check;
+mod coerce;
 mod collect;
+mod concurrent;
 mod dealloc;
+mod dot;
+mod finalize;
+mod header;
+mod policy;
+mod registry;
+mod slice;
+mod stats;
 #[cfg(test)]
 mod tests;
 mod trace;
-use std::{cell::Cell, fmt::Debug, ops::Deref, ptr::NonNull, sync::Arc};
-
+mod verify;
+use std::{
+    alloc::Layout,
+    any::TypeId,
+    cell::UnsafeCell,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    mem::{self, ManuallyDrop},
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+pub use any::CcAny;
+pub use atomic::{Acc, AtomicCycleCollector, Mutator};
 pub use box_ptr::{collect_cycles, CcBoxPtr};
+pub use check::TraceError;
 use collect::RootsRef;
 pub use collect::{CcPtr, CycleCollector};
+pub use concurrent::{Ccc, ConcurrentCollector, DEFAULT_EPOCH_INTERVAL};
 
 use dealloc::deallocate;
-pub use trace::{Trace, Tracer};
+pub use finalize::Finalize;
+pub use header::CcBoxMetaData;
+use header::VTable;
+pub use policy::{CollectionPolicy, DEFAULT_THRESHOLD};
+pub use stats::CollectionStats;
+pub use trace::{untraceable, Trace, Tracer};
+pub use verify::{Violation, ViolationKind};
+
+#[cfg(feature = "derive")]
+pub use cc_bacon_derive::Trace;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
@@ -30,104 +69,111 @@ pub enum Color {
     /// Acyclic
     Green,
 
-    /// Candidate cycle undergoing -computation
+    /// Candidate cycle undergoing Σ-computation
     Red,
 
     /// Candidate cycle awaiting epoch boundary
     Orange,
 }
 
-#[doc(hidden)]
-pub struct CcBoxMetaData {
-    strong: Cell<usize>,
-    weak: Cell<usize>,
-    buffered: Cell<bool>,
-    color: Cell<Color>,
-    root: Arc<CycleCollector>,
-}
-
-impl Debug for CcBoxMetaData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Metadata")
-            .field("strong", &self.strong.get())
-            .field("weak", &self.weak.get())
-            .field("buffered", &self.buffered.get())
-            .field("color", &self.color.get())
-            .finish()
-    }
-}
-
-impl CcBoxMetaData {
-    /*
-    There is an implicit weak pointer owned by all the strong
-    pointers, which ensures that the weak destructor never frees
-    the allocation while the strong destructor is running, even
-    if the weak pointer is stored inside the strong one.
-    */
-    pub fn with(root: Arc<CycleCollector>) -> Self {
-        Self {
-            strong: 1.into(),
-            weak: 1.into(),
-            buffered: false.into(),
-            color: Color::Black.into(),
-            root,
-        }
-    }
+impl Color {
+    /// Every color, indexed by its discriminant.
+    pub(crate) const ALL: [Color; 7] = [
+        Color::Black,
+        Color::Gray,
+        Color::White,
+        Color::Purple,
+        Color::Green,
+        Color::Red,
+        Color::Orange,
+    ];
 }
 
 /// TODO: impl !Send !Sync for CcBox&Cc
-struct CcBox<T: Trace> {
-    value: T,
+///
+/// Public only for [`cc_coerce!`] to name.
+///
+/// The metadata comes first, so that a pointer to it is a pointer to the box.
+#[doc(hidden)]
+#[repr(C)]
+pub struct CcBox<T: ?Sized + Trace> {
     metadata: CcBoxMetaData,
+    /// Dropped in place once the strong count reaches zero, which can happen
+    /// long before the allocation itself is freed if weak pointers remain.
+    value: UnsafeCell<ManuallyDrop<T>>,
 }
 
-impl<T: Trace> Trace for Cc<T> {
+impl<T: ?Sized + Trace> CcBox<T> {
+    #[inline(always)]
+    fn value(&self) -> &T {
+        unsafe { &*self.value.get() }
+    }
+}
+
+unsafe impl<T: ?Sized + Trace> Trace for Cc<T> {
     fn trace(&self, tracer: &mut Tracer) {
-        #[cfg(test)]
-        println!("Call trace from Cc: {:?}", self.get_ptr());
         /*Trace::trace(unsafe {self._ptr.as_ref() }, tracer)
          */
         unsafe {
-            tracer(self._ptr.as_ref());
+            tracer(&self._ptr.as_ref().metadata);
         }
     }
 }
 
-impl<T: 'static + Trace> Trace for CcBox<T> {
-    fn trace(&self, tracer: &mut Tracer) {
-        #[cfg(test)]
-        println!("Call trace from CcBox: {:?}", self.get_ptr());
-        Trace::trace(&self.value, tracer)
+impl<T: 'static + Trace> CcBox<T> {
+    const VTABLE: VTable = VTable {
+        trace: Self::trace_value,
+        drop_value: Self::drop_value,
+        layout: Self::layout,
+        type_name: std::any::type_name::<T>,
+        type_id: TypeId::of::<T>,
+    };
+
+    /// # Safety
+    ///
+    /// `s` must be the metadata of a `CcBox<T>`.
+    #[inline(always)]
+    unsafe fn from_metadata(s: &CcBoxMetaData) -> &Self {
+        &*(s as *const CcBoxMetaData as *const Self)
     }
-}
 
-impl<T: 'static + Trace> CcBoxPtr for CcBox<T> {
-    fn metadata(&self) -> &CcBoxMetaData {
-        &self.metadata
+    unsafe fn trace_value(s: &CcBoxMetaData, tracer: &mut Tracer) {
+        Self::from_metadata(s).value().trace(tracer)
     }
 
-    fn get_ptr(&self) -> CcPtr {
-        // CcBox's mutability is interior, so?
-        NonNull::from(self)
+    unsafe fn drop_value(s: &CcBoxMetaData) {
+        ManuallyDrop::drop(&mut *Self::from_metadata(s).value.get());
+    }
+
+    unsafe fn layout(_: &CcBoxMetaData) -> Layout {
+        Layout::new::<Self>()
     }
 }
 
 #[doc(hidden)]
-impl<T: Trace> CcBoxPtr for Cc<T> {
+impl<T: ?Sized + Trace> CcBoxPtr for Cc<T> {
     #[inline(always)]
     fn metadata(&self) -> &CcBoxMetaData {
-        unsafe { self._ptr.as_ref().metadata() }
+        unsafe { &self._ptr.as_ref().metadata }
     }
 
     fn get_ptr(&self) -> CcPtr {
-        self._ptr
+        self._ptr.cast()
+    }
+
+    unsafe fn drop_value(&self) {
+        self.metadata().drop_value()
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
     }
 }
 
 /// A reference-counted pointer type over an immutable value.
 ///
 /// See the [module level documentation](./) for more details.
-pub struct Cc<T: 'static + Trace> {
+pub struct Cc<T: 'static + ?Sized + Trace> {
     // FIXME #12808: strange names to try to avoid interfering with field
     // accesses of the contained type via Deref
     _ptr: NonNull<CcBox<T>>,
@@ -135,48 +181,240 @@ pub struct Cc<T: 'static + Trace> {
 
 impl<T: Trace> Cc<T> {
     pub fn new(value: T, roots: &RootsRef) -> Cc<T> {
+        Cc::new_cyclic(|_| value, roots)
+    }
+
+    /// Build a value that can hold a `Weak` to its own box, like
+    /// [`Rc::new_cyclic`](std::rc::Rc::new_cyclic).
+    ///
+    /// The box has no strong reference until `data_fn` returns, so upgrading
+    /// the `Weak` inside `data_fn` gives `None`.
+    pub fn new_cyclic(data_fn: impl FnOnce(&Weak<T>) -> T, roots: &RootsRef) -> Cc<T> {
+        let metadata =
+            CcBoxMetaData::for_new_box(roots, &CcBox::<T>::VTABLE, std::mem::size_of::<CcBox<T>>());
+        metadata.set_strong(0);
+        // The value is written once `data_fn` returns. Until then the only
+        // reference is `weak`, which owns the implicit weak count and frees
+        // the box if `data_fn` panics.
+        let ptr = Box::into_raw(Box::<CcBox<T>>::new_uninit()).cast::<CcBox<T>>();
+        unsafe { ptr::addr_of_mut!((*ptr).metadata).write(metadata) };
+        let weak = Weak {
+            _ptr: unsafe { NonNull::new_unchecked(ptr) },
+        };
+
+        let value = data_fn(&weak);
+        if T::is_acyclic() {
+            debug_assert!(
+                {
+                    let mut owns_cc = false;
+                    value.trace(&mut |_| owns_cc = true);
+                    !owns_cc
+                },
+                "acyclic type {} owns a Cc",
+                std::any::type_name::<T>()
+            );
+            weak.metadata().set_color(Color::Green);
+        }
         unsafe {
-            Cc {
-                _ptr: NonNull::new_unchecked(Box::into_raw(Box::new(CcBox {
-                    value,
-                    metadata: CcBoxMetaData::with(roots.clone()),
-                }))),
-            }
+            ptr::addr_of_mut!((*ptr).value).write(UnsafeCell::new(ManuallyDrop::new(value)));
         }
+        weak.metadata().set_strong(1);
+        mem::forget(weak);
+        Cc {
+            _ptr: unsafe { NonNull::new_unchecked(ptr) },
+        }
+    }
+
+    /// Like [`Cc::new`], but `value`'s [`Finalize`] impl runs before it is
+    /// dropped.
+    pub fn new_finalizable(value: T, roots: &RootsRef) -> Cc<T>
+    where
+        T: Finalize,
+    {
+        let cc = Cc::new(value, roots);
+        roots.finalizers.register(unsafe { cc._ptr.as_ref() });
+        cc
+    }
+
+    /// Move the value out if `this` is its only strong reference.
+    ///
+    /// A finalizer the value was created with doesn't run.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this.strong() != 1 || this.metadata().torn_down() {
+            return Err(this);
+        }
+        let this = ManuallyDrop::new(this);
+        let id = this.metadata().root;
+        let value = unsafe { ManuallyDrop::take(&mut *this._ptr.as_ref().value.get()) };
+        this.metadata().root().release_moved(this.get_ptr());
+        id.unregister_if_unused();
+        Ok(value)
+    }
+
+    /// Like [`Cc::try_unwrap`], but drops `this` when there are other strong
+    /// references.
+    pub fn into_inner(this: Self) -> Option<T> {
+        Cc::try_unwrap(this).ok()
     }
 
+    /// A mutable reference to the value, cloning it into a new box first if
+    /// other `Cc`s point to it. If only `Weak`s do, the value is moved to a
+    /// new box instead and they can't be upgraded anymore.
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if this.strong() != 1 || this.metadata().torn_down() {
+            let roots = this.metadata().root.collector().expect("collector");
+            *this = Cc::new((**this).clone(), &roots);
+        } else if this.weak() != 1 {
+            let roots = this.metadata().root.collector().expect("collector");
+            let value = unsafe { ManuallyDrop::take(&mut *this._ptr.as_ref().value.get()) };
+            roots.release_moved(this.get_ptr());
+            unsafe { ptr::write(this, Cc::new(value, &roots)) };
+        }
+        unsafe { &mut *this._ptr.as_ref().value.get() }
+    }
+}
+
+impl<T: ?Sized + Trace> Cc<T> {
     pub fn downgrade(&self) -> Weak<T> {
         self.inc_weak();
         Weak { _ptr: self._ptr }
     }
+
+    /// Whether both `Cc`s point to the same box.
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        ptr::addr_eq(this._ptr.as_ptr(), other._ptr.as_ptr())
+    }
+
+    #[inline]
+    pub fn strong_count(this: &Self) -> usize {
+        this.strong()
+    }
+
+    /// The number of `Weak`s pointing to this box.
+    #[inline]
+    pub fn weak_count(this: &Self) -> usize {
+        // Without the implicit one owned by the strong pointers.
+        this.weak() - 1
+    }
+
+    /// A mutable reference to the value, if there is no other `Cc` or `Weak`
+    /// pointing to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.strong() == 1 && this.weak() == 1 && !this.metadata().torn_down() {
+            Some(unsafe { &mut *this._ptr.as_ref().value.get() })
+        } else {
+            None
+        }
+    }
 }
 
-impl<T: Trace> Deref for Cc<T> {
+impl<T: ?Sized + Trace> Deref for Cc<T> {
     type Target = T;
 
     #[inline(always)]
     fn deref(&self) -> &T {
-        if self.strong() > 0 {
-            unsafe { &self._ptr.as_ref().value }
+        if self.strong() > 0 && !self.metadata().torn_down() {
+            unsafe { self._ptr.as_ref().value() }
         } else {
             panic!("Invalid access during cycle collection");
         }
     }
 }
 
-impl<T: Trace> Clone for Cc<T> {
+impl<T: ?Sized + Trace> Clone for Cc<T> {
+    /// # Panics
+    ///
+    /// When called from the `Drop` impl of a member of a garbage cycle on a
+    /// `Cc` to another member, whose value may already be dropped.
     fn clone(&self) -> Self {
+        assert!(
+            !self.metadata().torn_down(),
+            "cloned a Cc<{}> to a garbage cycle being collected",
+            self.type_name()
+        );
         self.inc_strong();
         Cc { _ptr: self._ptr }
     }
 }
 
-impl<T: Trace> Drop for Cc<T> {
+impl<T: ?Sized + Trace + PartialEq> PartialEq for Cc<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Trace + Eq> Eq for Cc<T> {}
+
+impl<T: ?Sized + Trace + PartialOrd> PartialOrd for Cc<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Trace + Ord> Ord for Cc<T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Trace + Hash> Hash for Cc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized + Trace + fmt::Display> fmt::Display for Cc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + Trace + fmt::Debug> fmt::Debug for Cc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + Trace> fmt::Pointer for Cc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&ptr::addr_of!(**self), f)
+    }
+}
+
+impl<T: ?Sized + Trace> std::borrow::Borrow<T> for Cc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized + Trace> AsRef<T> for Cc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized + Trace> Drop for Cc<T> {
     fn drop(&mut self) {
-        #[cfg(test)]
-        dbg!("Cc Drop here.");
+        let id = self.metadata().root;
+        // `decrement` may free the box, but the collector stays registered
+        // until `unregister_if_unused`.
+        let Some(root) = (unsafe { id.get() }) else {
+            // Too late to do anything on an exiting thread, so just leak.
+            return;
+        };
+        if root.defer_decrement(self) {
+            return;
+        }
         CycleCollector::decrement(self);
-        self.metadata().root.collect_cycles();
+        root.maybe_collect();
+        id.unregister_if_unused();
     }
 }
 
@@ -186,50 +424,115 @@ impl<T: Trace> Drop for Cc<T> {
 /// dropped.
 ///
 /// See the [module level documentation](./) for more.
-pub struct Weak<T: 'static + Trace> {
+pub struct Weak<T: 'static + ?Sized + Trace> {
     // FIXME #12808: strange names to try to avoid interfering with
     // field accesses of the contained type via Deref
     _ptr: NonNull<CcBox<T>>,
 }
 
-impl<T: 'static + Trace> Trace for Weak<T> {
+unsafe impl<T: 'static + ?Sized + Trace> Trace for Weak<T> {
     fn trace(&self, _tracer: &mut Tracer) {
         // Weak references should not be traced.
     }
-}
 
+    fn is_acyclic() -> bool {
+        true
+    }
+}
 
-impl<T: 'static + Trace> CcBoxPtr for Weak<T> {
+impl<T: 'static + ?Sized + Trace> CcBoxPtr for Weak<T> {
     fn metadata(&self) -> &CcBoxMetaData {
-        unsafe { self._ptr.as_ref().metadata() }
+        self.inner().expect("Weak::new() has no box")
     }
 
     fn get_ptr(&self) -> CcPtr {
         // when weak exist, there are no risk of dangling pointer because actual deallocate havn't happen
-        self._ptr
+        self._ptr.cast()
+    }
+
+    unsafe fn drop_value(&self) {
+        self.metadata().drop_value()
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
     }
 }
 
 impl<T: 'static + Trace> Weak<T> {
+    /// A `Weak` without a box, which never upgrades.
+    pub fn new() -> Weak<T> {
+        Weak {
+            _ptr: unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(usize::MAX)) },
+        }
+    }
+}
+
+impl<T: 'static + ?Sized + Trace> Weak<T> {
+    /// The box's metadata, unless this came from [`Weak::new`].
+    #[inline]
+    fn inner(&self) -> Option<&CcBoxMetaData> {
+        if self._ptr.addr().get() == usize::MAX {
+            None
+        } else {
+            Some(unsafe { &self._ptr.as_ref().metadata })
+        }
+    }
+
     pub fn upgrade(&self) -> Option<Cc<T>> {
-        if self.strong() == 0 {
+        let inner = self.inner()?;
+        if inner.strong() == 0 || inner.metadata().torn_down() {
             None
         } else {
             self.inc_strong();
             Some(Cc { _ptr: self._ptr })
         }
     }
+
+    /// Whether both `Weak`s point to the same box, or were both created by
+    /// [`Weak::new`].
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self._ptr.as_ptr(), other._ptr.as_ptr())
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner().map_or(0, |inner| inner.strong())
+    }
+}
+
+impl<T: 'static + Trace> Default for Weak<T> {
+    fn default() -> Self {
+        Weak::new()
+    }
+}
+
+impl<T: 'static + ?Sized + Trace> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+impl<T: 'static + ?Sized + Trace> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if self.inner().is_some() {
+            self.inc_weak();
+        }
+        Weak { _ptr: self._ptr }
+    }
 }
 
-impl<T: 'static + Trace> Drop for Weak<T> {
+impl<T: 'static + ?Sized + Trace> Drop for Weak<T> {
     fn drop(&mut self) {
-        if self.weak() > 0 {
+        if self.inner().is_some() && self.weak() > 0 {
             self.dec_weak();
             // The weak count starts at 1, and will only go to zero if all
             // the strong pointers have disappeared.
             if self.weak() == 0 {
                 debug_assert_eq!(self.strong(), 0);
+                let id = self.metadata().root;
                 unsafe { deallocate(self.get_ptr()) }
+                id.unregister_if_unused();
             }
         }
     }
@@ -0,0 +1,111 @@
+use std::cell::Cell;
+
+/// Roots buffer size the default policy collects at, after CPython's
+/// generation 0 threshold.
+pub const DEFAULT_THRESHOLD: usize = 700;
+
+/// Decides when a [`CycleCollector`](crate::CycleCollector) runs
+/// `collect_cycles` on its own.
+///
+/// Explicit calls to `collect_cycles` always collect, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionPolicy {
+    /// Never collect automatically.
+    Manual,
+
+    /// Collect once the roots buffer holds this many possible roots.
+    RootBuffer(usize),
+
+    /// Collect after this many `Cc` allocations since the last collection.
+    Allocations(usize),
+
+    /// Collect after this many bytes of `Cc` boxes were allocated since the
+    /// last collection.
+    Bytes(usize),
+
+    /// Like `RootBuffer`, starting at `min`, but the threshold doubles (up to
+    /// `max`) when a collection finds little garbage and halves (down to
+    /// `min`) when it finds a lot.
+    Adaptive { min: usize, max: usize },
+}
+
+impl Default for CollectionPolicy {
+    fn default() -> Self {
+        Self::RootBuffer(DEFAULT_THRESHOLD)
+    }
+}
+
+/// Bookkeeping a [`CollectionPolicy`] needs between two collections.
+#[derive(Debug)]
+pub(crate) struct Trigger {
+    policy: Cell<CollectionPolicy>,
+    allocations: Cell<usize>,
+    bytes: Cell<usize>,
+    /// Current roots buffer threshold of an `Adaptive` policy.
+    threshold: Cell<usize>,
+}
+
+impl Trigger {
+    pub fn new(policy: CollectionPolicy) -> Self {
+        let trigger = Self {
+            policy: Cell::new(policy),
+            allocations: 0.into(),
+            bytes: 0.into(),
+            threshold: 0.into(),
+        };
+        trigger.set_policy(policy);
+        trigger
+    }
+
+    pub fn policy(&self) -> CollectionPolicy {
+        self.policy.get()
+    }
+
+    pub fn set_policy(&self, policy: CollectionPolicy) {
+        if let CollectionPolicy::Adaptive { min, .. } = policy {
+            self.threshold.set(min);
+        }
+        self.policy.set(policy);
+    }
+
+    #[inline]
+    pub fn note_allocation(&self, bytes: usize) {
+        self.allocations.set(self.allocations.get() + 1);
+        self.bytes.set(self.bytes.get() + bytes);
+    }
+
+    /// Whether the policy asks for a collection given `roots` buffered
+    /// possible roots.
+    #[inline]
+    pub fn should_collect(&self, roots: usize) -> bool {
+        match self.policy.get() {
+            CollectionPolicy::Manual => false,
+            CollectionPolicy::RootBuffer(threshold) => roots >= threshold,
+            CollectionPolicy::Allocations(threshold) => self.allocations.get() >= threshold,
+            CollectionPolicy::Bytes(threshold) => self.bytes.get() >= threshold,
+            CollectionPolicy::Adaptive { .. } => roots >= self.threshold.get(),
+        }
+    }
+
+    /// Reset the counters after a collection that examined `roots` possible
+    /// roots and freed `freed` objects.
+    pub fn collected(&self, roots: usize, freed: usize) {
+        self.allocations.set(0);
+        self.bytes.set(0);
+        if let CollectionPolicy::Adaptive { min, max } = self.policy.get() {
+            let threshold = self.threshold.get();
+            if freed.saturating_mul(4) < roots {
+                self.threshold
+                    .set(threshold.saturating_mul(2).min(max).max(min));
+            } else if freed.saturating_mul(2) > roots {
+                self.threshold.set((threshold / 2).max(min));
+            }
+        }
+    }
+
+    /// Current roots buffer threshold of an `Adaptive` policy.
+    #[cfg(test)]
+    pub fn threshold(&self) -> usize {
+        self.threshold.get()
+    }
+}
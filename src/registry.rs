@@ -0,0 +1,128 @@
+//! How a box finds its [`CycleCollector`] without holding an `Arc` to it.
+//!
+//! `Cc`s never leave their thread, so every collector with live boxes is kept
+//! in a thread-local table, and the boxes only store their collector's index
+//! into it. The table owns a strong reference to each collector for as long
+//! as it has any box allocated, and gives it up when the last one goes.
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::CycleCollector;
+
+/// Index of a collector in this thread's registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CollectorId(u32);
+
+thread_local! {
+    static COLLECTORS: RefCell<Registry> = const {
+        RefCell::new(Registry {
+            slots: Vec::new(),
+            free: Vec::new(),
+        })
+    };
+}
+
+struct Registry {
+    slots: Vec<Option<Arc<CycleCollector>>>,
+    /// Indices of the empty slots.
+    free: Vec<u32>,
+}
+
+impl Registry {
+    /// Take out the collectors that have no box allocated and nobody else
+    /// referring to them, e.g. after an explicit `collect_cycles` freed their
+    /// last boxes.
+    fn sweep(&mut self) -> Vec<Arc<CycleCollector>> {
+        let mut unused = Vec::new();
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if slot
+                .as_ref()
+                .is_some_and(|root| root.live.get() == 0 && Arc::strong_count(root) == 1)
+            {
+                let root = slot.take().unwrap();
+                root.id.set(None);
+                unused.push(root);
+                self.free.push(i as u32);
+            }
+        }
+        unused
+    }
+}
+
+impl CollectorId {
+    /// The id of `root`, registering it if it has no live boxes yet.
+    pub fn of(root: &Arc<CycleCollector>) -> Self {
+        if let Some(id) = root.id.get() {
+            return id;
+        }
+        let (id, unused) = COLLECTORS.with(|registry| {
+            let mut registry = registry.borrow_mut();
+            let unused = registry.sweep();
+            let id = match registry.free.pop() {
+                Some(i) => i,
+                None => {
+                    registry.slots.push(None);
+                    u32::try_from(registry.slots.len() - 1).expect("too many collectors")
+                }
+            };
+            registry.slots[id as usize] = Some(root.clone());
+            (CollectorId(id), unused)
+        });
+        drop(unused);
+        root.id.set(Some(id));
+        id
+    }
+
+    /// The collector with this id, or `None` if the registry is already gone
+    /// because the thread is exiting.
+    ///
+    /// # Safety
+    ///
+    /// The reference is valid while the collector has a box allocated, and
+    /// after that until `unregister_if_unused`.
+    pub unsafe fn get<'a>(self) -> Option<&'a CycleCollector> {
+        COLLECTORS
+            .try_with(|registry| {
+                let registry = registry.borrow();
+                let root = registry.slots[self.0 as usize]
+                    .as_ref()
+                    .expect("collector of a live box is registered");
+                &*Arc::as_ptr(root)
+            })
+            .ok()
+    }
+
+    /// A strong reference to the collector with this id, for allocating a
+    /// box next to one that may go away in the meantime.
+    pub fn collector(self) -> Option<Arc<CycleCollector>> {
+        COLLECTORS
+            .try_with(|registry| registry.borrow().slots[self.0 as usize].clone())
+            .ok()
+            .flatten()
+    }
+
+    /// Give up the registry's reference to the collector once it has no box
+    /// allocated anymore, which may drop it.
+    ///
+    /// Called when done with a `Cc` or `Weak` operation, so that nothing up
+    /// the stack is still using the collector.
+    pub fn unregister_if_unused(self) {
+        let _ = COLLECTORS.try_with(|registry| {
+            let root = {
+                let mut registry = registry.borrow_mut();
+                let slot = &mut registry.slots[self.0 as usize];
+                // Still in use, or already given up when done with another
+                // handle.
+                let Some(root) = slot.take_if(|root| root.live.get() == 0) else {
+                    return;
+                };
+                registry.free.push(self.0);
+                root
+            };
+            root.id.set(None);
+            // Dropped with the registry released, in case this was the last
+            // reference.
+            drop(root);
+        });
+    }
+}
@@ -0,0 +1,120 @@
+//! `Cc`s of slices and `str`.
+//!
+//! The length of the value is kept in the box's header, so that the
+//! collector can trace, drop and free it through a thin pointer like any
+//! other box.
+use std::alloc::{alloc, handle_alloc_error, Layout};
+use std::any::TypeId;
+use std::mem::ManuallyDrop;
+use std::ptr::{self, NonNull};
+
+use crate::collect::RootsRef;
+use crate::header::VTable;
+use crate::{Cc, CcBox, CcBoxMetaData, Color, Trace, Tracer};
+
+impl<T: 'static + Trace> CcBox<[T]> {
+    const VTABLE: VTable = VTable {
+        trace: Self::trace_value,
+        drop_value: Self::drop_value,
+        layout: Self::layout,
+        type_name: std::any::type_name::<[T]>,
+        type_id: TypeId::of::<[T]>,
+    };
+
+    fn layout_for(len: usize) -> Layout {
+        Layout::new::<CcBoxMetaData>()
+            .extend(Layout::array::<T>(len).expect("slice too large"))
+            .expect("slice too large")
+            .0
+            .pad_to_align()
+    }
+
+    /// # Safety
+    ///
+    /// `s` must be the metadata of a `CcBox<[T]>`.
+    #[inline(always)]
+    unsafe fn from_metadata(s: &CcBoxMetaData) -> &Self {
+        let elements =
+            ptr::slice_from_raw_parts(s as *const CcBoxMetaData as *const T, s.len as usize);
+        &*(elements as *const Self)
+    }
+
+    unsafe fn trace_value(s: &CcBoxMetaData, tracer: &mut Tracer) {
+        Self::from_metadata(s).value().trace(tracer)
+    }
+
+    unsafe fn drop_value(s: &CcBoxMetaData) {
+        ManuallyDrop::drop(&mut *Self::from_metadata(s).value.get());
+    }
+
+    unsafe fn layout(s: &CcBoxMetaData) -> Layout {
+        Self::layout_for(s.len as usize)
+    }
+}
+
+impl CcBox<str> {
+    const VTABLE: VTable = VTable {
+        type_name: std::any::type_name::<str>,
+        type_id: TypeId::of::<str>,
+        ..CcBox::<[u8]>::VTABLE
+    };
+}
+
+impl<T: 'static + Trace> Cc<[T]> {
+    /// Collect `iter` into a slice in a new box.
+    pub fn from_iter(iter: impl IntoIterator<Item = T>, roots: &RootsRef) -> Cc<[T]> {
+        Self::from_vec(iter.into_iter().collect(), roots, &CcBox::<[T]>::VTABLE)
+    }
+
+    /// Move the elements of `value` into a new box.
+    pub fn from_box(value: Box<[T]>, roots: &RootsRef) -> Cc<[T]> {
+        Self::from_vec(value.into_vec(), roots, &CcBox::<[T]>::VTABLE)
+    }
+
+    fn from_vec(mut vec: Vec<T>, roots: &RootsRef, vtable: &'static VTable) -> Cc<[T]> {
+        let len = vec.len();
+        let layout = CcBox::<[T]>::layout_for(len);
+        let mut metadata = CcBoxMetaData::for_new_box(roots, vtable, layout.size());
+        metadata.len = u32::try_from(len).expect("slice too long for a Cc");
+        if T::is_acyclic() {
+            metadata.set_color(Color::Green);
+        }
+        unsafe {
+            let mem = alloc(layout);
+            if mem.is_null() {
+                handle_alloc_error(layout);
+            }
+            let ptr = ptr::slice_from_raw_parts_mut(mem as *mut T, len) as *mut CcBox<[T]>;
+            ptr::addr_of_mut!((*ptr).metadata).write(metadata);
+            ptr::copy_nonoverlapping(vec.as_ptr(), ptr::addr_of_mut!((*ptr).value) as *mut T, len);
+            // The elements were moved into the box.
+            vec.set_len(0);
+            Cc {
+                _ptr: NonNull::new_unchecked(ptr),
+            }
+        }
+    }
+}
+
+impl Cc<str> {
+    /// Copy `value` into a new box of `roots`.
+    ///
+    /// This can't be `From<&str>`, whose `from` has no room for the
+    /// collector.
+    pub fn from_str_in(value: &str, roots: &RootsRef) -> Cc<str> {
+        Self::from_bytes(value.as_bytes().to_vec(), roots)
+    }
+
+    /// Move `value` into a new box.
+    pub fn from_box(value: Box<str>, roots: &RootsRef) -> Cc<str> {
+        Self::from_bytes(value.into_boxed_bytes().into_vec(), roots)
+    }
+
+    /// `bytes` must be UTF-8.
+    fn from_bytes(bytes: Vec<u8>, roots: &RootsRef) -> Cc<str> {
+        let bytes = ManuallyDrop::new(Cc::from_vec(bytes, roots, &CcBox::<str>::VTABLE));
+        Cc {
+            _ptr: unsafe { NonNull::new_unchecked(bytes._ptr.as_ptr() as *mut CcBox<str>) },
+        }
+    }
+}
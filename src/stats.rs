@@ -0,0 +1,48 @@
+use std::{ops::AddAssign, time::Duration};
+
+/// What a [`CycleCollector`](crate::CycleCollector) did during one
+/// `collect_cycles` call, or summed over all of them by
+/// [`CycleCollector::stats`](crate::CycleCollector::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    /// Collections these numbers cover.
+    pub collections: usize,
+
+    /// Possible roots taken from the roots buffer.
+    pub roots: usize,
+
+    /// Objects colored Gray during trial deletion.
+    pub marked_gray: usize,
+
+    /// Objects whose `trace` couldn't reach everything they own, see
+    /// [`untraceable`](crate::untraceable); they were kept alive.
+    pub untraceable: usize,
+
+    /// Objects left White after scanning, i.e. members of garbage cycles.
+    pub white: usize,
+
+    /// Objects whose value was dropped and whose box was given up: the white
+    /// ones, plus released ones that were waiting in the roots buffer.
+    ///
+    /// The memory itself stays allocated while `Weak`s to it remain.
+    pub freed: usize,
+
+    /// Size of the boxes counted in `freed`.
+    pub bytes_freed: usize,
+
+    /// Wall-clock time spent collecting.
+    pub elapsed: Duration,
+}
+
+impl AddAssign for CollectionStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.collections += rhs.collections;
+        self.roots += rhs.roots;
+        self.marked_gray += rhs.marked_gray;
+        self.untraceable += rhs.untraceable;
+        self.white += rhs.white;
+        self.freed += rhs.freed;
+        self.bytes_freed += rhs.bytes_freed;
+        self.elapsed += rhs.elapsed;
+    }
+}
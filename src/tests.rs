@@ -1,12 +1,24 @@
-use std::{sync::Arc, cell::RefCell};
+#![allow(clippy::arc_with_non_send_sync)]
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
 
 use super::*;
+use cc_bacon_derive::Trace;
 
 struct TestObj{
     to: RefCell<Option<Cc<TestObj>>>
 }
 
-impl Trace for TestObj{
+unsafe impl Trace for TestObj{
     fn trace(&self, tracer: &mut Tracer) {
         if let Some(to) = self.to.borrow().into(){
             to.trace(tracer)
@@ -29,33 +41,2110 @@ fn test_dead() {
 }
 
 #[test]
-fn test_self_ref_cc(){
-    let root = Arc::new(CycleCollector::new());
-    // let _five = Cc::new(5i32, &root);
-    let cycle = Cc::new(TestObj{to:None.into()}, &root);
+fn test_self_ref_cc() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let cycle = Cc::new(TestObj { to: None.into() }, &root);
     *cycle.to.borrow_mut() = Some(cycle.clone());
-    //drop(cycle);
-    //root.collect_cycles();
-    dbg!(root);
+    assert_eq!(Cc::strong_count(&cycle), 2);
+    let weak = cycle.downgrade();
+    drop(cycle);
+    assert!(weak.upgrade().is_some());
+    let stats = root.collect_cycles();
+    assert_eq!((stats.roots, stats.white, stats.freed), (1, 1, 1));
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_simple_ref_cycle() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let obj1 = Cc::new(TestObj { to: None.into() }, &root);
+    let obj2 = Cc::new(TestObj { to: None.into() }, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    assert_eq!(Cc::strong_count(&obj1), 2);
+    assert_eq!(Cc::strong_count(&obj2), 2);
+    let weak = obj2.downgrade();
+    drop(obj2);
+    assert_eq!(weak.strong_count(), 1);
+    // Still reachable from obj1.
+    assert_eq!(root.collect_cycles().freed, 0);
+    assert!(weak.upgrade().is_some());
+    drop(obj1);
+    let stats = root.collect_cycles();
+    assert_eq!((stats.white, stats.freed), (2, 2));
+    assert!(weak.upgrade().is_none());
+}
+
+/// Counts the drops of the values sharing it, from any thread.
+#[derive(Clone, Default)]
+struct Drops(Arc<AtomicUsize>);
+
+impl Drops {
+    fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Picks the edges of a [`Node`], which may point back at `Node<Self>`.
+trait Kind: 'static {
+    type Edges: Trace;
+}
+
+/// The value most tests build their heaps from: some edges, and a count of
+/// its drops.
+struct Node<K: Kind> {
+    edges: K::Edges,
+    drops: Drops,
+}
+
+unsafe impl<K: Kind> Trace for Node<K> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.edges.trace(tracer);
+    }
+}
+
+impl<K: Kind> Node<K> {
+    fn with(edges: K::Edges, drops: &Drops) -> Node<K> {
+        Node {
+            edges,
+            drops: drops.clone(),
+        }
+    }
+}
+
+impl<K: Kind> Drop for Node<K> {
+    fn drop(&mut self) {
+        self.drops.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Any number of `Cc`s.
+enum Local {}
+
+impl Kind for Local {
+    type Edges = RefCell<Vec<Cc<Node<Local>>>>;
+}
+
+impl Node<Local> {
+    fn new(drops: &Drops, root: &RootsRef) -> Cc<Node<Local>> {
+        Cc::new(Node::with(Vec::new().into(), drops), root)
+    }
+
+    fn link(&self, to: &Cc<Node<Local>>) {
+        self.edges.borrow_mut().push(to.clone());
+    }
+}
+
+#[test]
+fn test_cycle_pointing_at_live_object() {
+    let root = Arc::new(CycleCollector::new());
+    let drops = Drops::default();
+    let live = Node::new(&drops, &root);
+    let a = Node::new(&drops, &root);
+    let b = Node::new(&drops, &root);
+    a.link(&b);
+    b.link(&a);
+    a.link(&live);
+    b.link(&live);
+    drop(a);
+    drop(b);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 2);
+    assert_eq!(live.strong(), 1);
+    drop(live);
+    assert_eq!(drops.get(), 3);
+}
+
+#[test]
+fn test_drop_value() {
+    let root = Arc::new(CycleCollector::new());
+    let drops = Drops::default();
+    let obj = Node::new(&drops, &root);
+    let weak = obj.downgrade();
+    drop(obj);
+    assert_eq!(drops.get(), 1);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_drop_cycle_values() {
+    let root = Arc::new(CycleCollector::new());
+    let drops = Drops::default();
+    let obj1 = Node::new(&drops, &root);
+    let obj2 = Node::new(&drops, &root);
+    obj1.link(&obj2);
+    obj2.link(&obj1);
+    let weak = obj1.downgrade();
+    drop(obj2);
+    assert_eq!(drops.get(), 0);
+    drop(obj1);
+    assert_eq!(drops.get(), 0);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 2);
+    assert!(weak.upgrade().is_none());
+}
+
+/// Make a two object cycle and drop both handles to it.
+fn make_garbage_cycle(drops: &Drops, root: &RootsRef) {
+    let obj1 = Node::new(drops, root);
+    let obj2 = Node::new(drops, root);
+    obj1.link(&obj2);
+    obj2.edges.borrow_mut().push(obj1);
+}
+
+#[test]
+fn test_manual_policy() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    for _ in 0..10 {
+        make_garbage_cycle(&drops, &root);
+    }
+    assert_eq!(drops.get(), 0);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 20);
+}
+
+#[test]
+fn test_root_buffer_policy() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::RootBuffer(3)));
+    let drops = Drops::default();
+    make_garbage_cycle(&drops, &root);
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 0);
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 6);
+}
+
+#[test]
+fn test_allocation_policies() {
+    let policy = CollectionPolicy::Allocations(4);
+    let root = Arc::new(CycleCollector::with_policy(policy));
+    let drops = Drops::default();
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 0);
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 4);
+
+    let size = std::mem::size_of::<CcBox<Node<Local>>>();
+    root.set_policy(CollectionPolicy::Bytes(4 * size));
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 4);
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(drops.get(), 8);
+}
+
+#[test]
+fn test_adaptive_threshold() {
+    let trigger = policy::Trigger::new(CollectionPolicy::Adaptive { min: 10, max: 40 });
+    assert!(!trigger.should_collect(9));
+    assert!(trigger.should_collect(10));
+    trigger.collected(10, 0);
+    assert_eq!(trigger.threshold(), 20);
+    trigger.collected(20, 1);
+    trigger.collected(40, 1);
+    assert_eq!(trigger.threshold(), 40);
+    trigger.collected(40, 40);
+    assert_eq!(trigger.threshold(), 20);
+    trigger.collected(20, 20);
+    trigger.collected(10, 10);
+    assert_eq!(trigger.threshold(), 10);
+}
+
+#[test]
+fn test_collection_stats() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    for _ in 0..3 {
+        make_garbage_cycle(&drops, &root);
+    }
+    // Buffered, but still alive.
+    let live = Node::new(&drops, &root);
+    drop(live.clone());
+    // Buffered, then released.
+    let dead = Node::new(&drops, &root);
+    drop(dead.clone());
+    drop(dead);
+
+    let size = std::mem::size_of::<CcBox<Node<Local>>>();
+    let stats = root.collect_cycles();
+    assert_eq!(stats.collections, 1);
+    assert_eq!(stats.roots, 5);
+    assert_eq!(stats.marked_gray, 7);
+    assert_eq!(stats.white, 6);
+    assert_eq!(stats.freed, 7);
+    assert_eq!(stats.bytes_freed, 7 * size);
+    assert_eq!(drops.get(), 7);
+
+    let again = root.collect_cycles();
+    assert_eq!(again.roots, 0);
+    assert_eq!(again.freed, 0);
+
+    let total = root.stats();
+    assert_eq!(total.collections, 2);
+    assert_eq!(total.freed, 7);
+    assert_eq!(total.bytes_freed, 7 * size);
+    assert_eq!(total.elapsed, stats.elapsed + again.elapsed);
+    drop(live);
+}
+
+#[test]
+fn test_write_dot() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let obj1 = Cc::new(TestObj { to: None.into() }, &root);
+    let obj2 = Cc::new(TestObj { to: None.into() }, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    drop(obj2);
+    let leaf = Cc::new(5i32, &root);
+
+    let mut out = Vec::new();
+    root.write_dot(&mut out, &[&leaf]).unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    assert!(dot.starts_with("digraph heap {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(dot.matches("cc_bacon::tests::TestObj").count(), 2);
+    assert_eq!(dot.matches("peripheries=2").count(), 1);
+    assert!(dot.contains("i32\\nstrong 1, weak 1\\nGreen\""));
+    assert!(dot.contains("strong 2, weak 1\\nBlack"));
+    assert!(dot.contains("strong 1, weak 1\\nPurple"));
+    assert_eq!(dot.matches(" -> ").count(), 2);
+    assert!(dot.contains("n0 -> n2;"));
+    assert!(dot.contains("n2 -> n0;"));
+}
+
+/// Writes the graph of its collector when dropped.
+struct DotOnDrop {
+    root: RootsRef,
+    failed: Rc<Cell<Option<bool>>>,
+}
+
+empty_trace!(DotOnDrop);
+
+impl Drop for DotOnDrop {
+    fn drop(&mut self) {
+        let written = self.root.write_dot(&mut io::sink(), &[]);
+        self.failed.set(Some(written.is_err()));
+    }
+}
+
+#[test]
+fn test_write_dot_reentrant() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let failed = Rc::new(Cell::new(None));
+    drop(Cc::new(
+        DotOnDrop {
+            root: root.clone(),
+            failed: failed.clone(),
+        },
+        &root,
+    ));
+    assert_eq!(failed.get(), Some(true));
+    assert!(root.write_dot(&mut io::sink(), &[]).is_ok());
+}
+
+/// Any number of `Ccc`s.
+enum Concurrent {}
+
+impl Kind for Concurrent {
+    type Edges = RwLock<Vec<Ccc<Node<Concurrent>>>>;
+}
+
+impl Node<Concurrent> {
+    fn ccc(drops: &Drops, collector: &ConcurrentCollector) -> Ccc<Node<Concurrent>> {
+        Ccc::new(Node::with(Vec::new().into(), drops), collector)
+    }
+
+    fn link(&self, to: &Ccc<Node<Concurrent>>) {
+        self.edges.write().unwrap().push(to.clone());
+    }
+}
+
+#[test]
+fn test_concurrent_acyclic() {
+    let collector = ConcurrentCollector::new();
+    let drops = Drops::default();
+    let head = Node::ccc(&drops, &collector);
+    head.link(&Node::ccc(&drops, &collector));
+    let clone = head.clone();
+    drop(head);
+    collector.collect();
+    assert_eq!(drops.get(), 0);
+    drop(clone);
+    collector.collect();
+    assert_eq!(drops.get(), 2);
+}
+
+#[test]
+fn test_concurrent_cycle() {
+    let collector = Arc::new(ConcurrentCollector::with_epoch_interval(
+        Duration::from_millis(1),
+    ));
+    let drops = Drops::default();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let collector = collector.clone();
+            let drops = drops.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    let obj1 = Node::ccc(&drops, &collector);
+                    let obj2 = Node::ccc(&drops, &collector);
+                    obj1.link(&obj2);
+                    obj2.link(&obj1);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    collector.collect();
+    assert_eq!(drops.get(), 400);
+}
+
+#[test]
+fn test_concurrent_live_cycle() {
+    let collector = ConcurrentCollector::new();
+    let drops = Drops::default();
+    let obj1 = Node::ccc(&drops, &collector);
+    let obj2 = Node::ccc(&drops, &collector);
+    obj1.link(&obj2);
+    obj2.link(&obj1);
+    let keep = thread::spawn(move || obj1).join().unwrap();
+    drop(obj2);
+    collector.collect();
+    assert_eq!(drops.get(), 0);
+    drop(keep);
+    collector.collect();
+    assert_eq!(drops.get(), 2);
+}
+
+#[test]
+fn test_concurrent_mutation() {
+    let collector = Arc::new(ConcurrentCollector::with_epoch_interval(Duration::ZERO));
+    let drops = Drops::default();
+    let shared = Node::ccc(&drops, &collector);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let collector = collector.clone();
+            let drops = drops.clone();
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    let obj = Node::ccc(&drops, &collector);
+                    obj.link(&shared);
+                    *shared.edges.write().unwrap() = vec![obj];
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    drop(shared);
+    collector.collect();
+    assert_eq!(drops.get(), 801);
+}
+
+/// Clones and drops its `Ccc`s when dropped.
+enum Cloning {}
+
+struct CloneOnDrop {
+    to: RwLock<Vec<Ccc<Node<Cloning>>>>,
+    live: Ccc<Node<Concurrent>>,
+}
+
+unsafe impl Trace for CloneOnDrop {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.to.trace(tracer);
+        self.live.trace(tracer);
+    }
+}
+
+impl Drop for CloneOnDrop {
+    fn drop(&mut self) {
+        drop(self.live.clone());
+        drop(self.to.read().unwrap().clone());
+    }
+}
+
+impl Kind for Cloning {
+    type Edges = CloneOnDrop;
+}
+
+#[test]
+fn test_concurrent_clone_in_drop() {
+    let collector = ConcurrentCollector::new();
+    let drops = Drops::default();
+    let live = Node::ccc(&drops, &collector);
+    let new = || {
+        let edges = CloneOnDrop {
+            to: Vec::new().into(),
+            live: live.clone(),
+        };
+        Ccc::new(Node::<Cloning>::with(edges, &drops), &collector)
+    };
+    drop(new());
+    let obj1 = new();
+    let obj2 = new();
+    obj1.edges.to.write().unwrap().push(obj2.clone());
+    obj2.edges.to.write().unwrap().push(obj1.clone());
+    drop(obj1);
+    drop(obj2);
+    collector.collect();
+    assert_eq!(drops.get(), 3);
+    drop(live);
+    collector.collect();
+    assert_eq!(drops.get(), 4);
+}
+
+#[test]
+fn test_concurrent_long_cycle() {
+    let collector = ConcurrentCollector::new();
+    let drops = Drops::default();
+    let first = Node::ccc(&drops, &collector);
+    let mut last = first.clone();
+    for _ in 1..100_000 {
+        let obj = Node::ccc(&drops, &collector);
+        obj.edges.write().unwrap().push(last);
+        last = obj;
+    }
+    first.edges.write().unwrap().push(last);
+    drop(first);
+    collector.collect();
+    assert_eq!(drops.get(), 100_000);
+}
+
+fn trace_twice<T: Trace>(value: &T, tracer: &mut Tracer) {
+    value.trace(tracer);
+    value.trace(tracer);
+}
+
+#[derive(Trace)]
+struct Derived<T: Copy + Trace + 'static, const N: usize, U = ()>
+where
+    U: Default + Trace + 'static,
+{
+    next: RefCell<Option<Cc<Derived<T, N, U>>>>,
+    #[trace(skip)]
+    _untraced: std::ops::Range<usize>,
+    #[trace(unsafe(with = trace_twice))]
+    twice: Option<Cc<i32>>,
+    #[trace(skip)]
+    values: [T; N],
+    extra: U,
+}
+
+#[test]
+fn test_derive_struct() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let obj = Cc::new(
+        Derived {
+            next: None.into(),
+            _untraced: 0..1,
+            twice: None,
+            values: [1u8, 2],
+            extra: 3u8,
+        },
+        &root,
+    );
+    *obj.next.borrow_mut() = Some(obj.clone());
+    let weak = obj.downgrade();
+    drop(obj);
+    root.collect_cycles();
+    assert!(weak.upgrade().is_none());
+
+    let five = Cc::new(5, &root);
+    let obj = Derived {
+        next: None.into(),
+        _untraced: 0..1,
+        twice: Some(five),
+        values: [(); 0],
+        extra: (),
+    };
+    let mut edges = 0;
+    Trace::trace(&obj, &mut |_| edges += 1);
+    assert_eq!(edges, 2);
+    assert_eq!(obj.values.len(), 0);
+    assert_eq!(obj.extra, ());
+    let by_ref = DerivedRef(&obj, None);
+    let mut edges = 0;
+    Trace::trace(&by_ref, &mut |_| edges += 1);
+    assert_eq!(edges, 0);
+    assert!(std::ptr::eq(by_ref.0, &obj));
+}
+
+#[derive(Trace)]
+struct DerivedRef<'a, T: 'a>(#[trace(skip)] &'a T, Option<Cc<i32>>);
+
+#[derive(Trace)]
+struct DerivedTuple(
+    pub RefCell<Option<Cc<DerivedEnum>>>,
+    #[trace(skip)] pub fn() -> u8,
+);
+
+#[derive(Trace)]
+enum DerivedEnum {
+    Leaf,
+    Tuple(DerivedTuple, #[trace(skip)] u8),
+    Named {
+        #[trace(skip)]
+        _id: usize,
+        next: DerivedTuple,
+        _map: std::collections::HashMap<u8, Vec<Cc<i32>>>,
+        drops: Node<Local>,
+    },
+}
+
+#[test]
+fn test_derive_enum() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let tuple = Cc::new(
+        DerivedEnum::Tuple(DerivedTuple(None.into(), || 0), 0),
+        &root,
+    );
+    let named = Cc::new(
+        DerivedEnum::Named {
+            _id: 0,
+            next: DerivedTuple(Some(tuple.clone()).into(), || 0),
+            _map: Default::default(),
+            drops: Node::with(Vec::new().into(), &drops),
+        },
+        &root,
+    );
+    if let DerivedEnum::Tuple(DerivedTuple(next, f), n) = &*tuple {
+        assert_eq!(f(), *n);
+        *next.borrow_mut() = Some(named.clone());
+    }
+    let _leaf = Cc::new(DerivedEnum::Leaf, &root);
+    drop(tuple);
+    drop(named);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 1);
+}
+
+/// A chain of `len` `TestObj`s, returning its head and tail.
+fn make_chain(len: usize, root: &RootsRef) -> (Cc<TestObj>, Cc<TestObj>) {
+    let tail = Cc::new(TestObj { to: None.into() }, root);
+    let mut head = tail.clone();
+    for _ in 1..len {
+        let to = Some(head).into();
+        head = Cc::new(TestObj { to }, root);
+    }
+    (head, tail)
+}
+
+#[test]
+fn test_long_chain() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let (head, tail) = make_chain(1_000_000, &root);
+    let weak = tail.downgrade();
+    drop(tail);
+    drop(head);
+    assert!(weak.upgrade().is_none());
+
+    let (head, tail) = make_chain(1_000_000, &root);
+    *tail.to.borrow_mut() = Some(head.clone());
+    let weak = tail.downgrade();
+    drop(tail);
+    drop(head);
+    assert!(weak.upgrade().is_some());
+    root.collect_cycles();
+    assert!(weak.upgrade().is_none());
+}
+
+/// Logs its finalization and drop, and may stash a clone of its neighbour
+/// while being finalized.
+#[derive(Trace)]
+struct Finalized {
+    to: RefCell<Option<Cc<Finalized>>>,
+    #[trace(skip)]
+    log: Rc<RefCell<Vec<&'static str>>>,
+    #[trace(skip)]
+    stash: Option<Rc<RefCell<Vec<Cc<Finalized>>>>>,
+}
+
+impl Finalize for Finalized {
+    fn finalize(&self) {
+        self.log.borrow_mut().push("finalize");
+        if let Some(to) = &*self.to.borrow() {
+            // Still alive, whatever order the cycle is finalized in.
+            assert!(to.to.borrow().is_some());
+            if let Some(stash) = &self.stash {
+                stash.borrow_mut().push(to.clone());
+            }
+        }
+    }
+}
+
+impl Drop for Finalized {
+    fn drop(&mut self) {
+        self.log.borrow_mut().push("drop");
+    }
+}
+
+fn make_finalized_cycle(
+    log: &Rc<RefCell<Vec<&'static str>>>,
+    stash: Option<&Rc<RefCell<Vec<Cc<Finalized>>>>>,
+    root: &RootsRef,
+) -> Weak<Finalized> {
+    let new = |stash: Option<&Rc<_>>| {
+        Cc::new_finalizable(
+            Finalized {
+                to: None.into(),
+                log: log.clone(),
+                stash: stash.cloned(),
+            },
+            root,
+        )
+    };
+    let obj1 = new(stash);
+    let obj2 = new(None);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    obj1.downgrade()
+}
+
+#[test]
+fn test_finalize_release() {
+    let root = Arc::new(CycleCollector::new());
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let obj = Cc::new_finalizable(
+        Finalized {
+            to: None.into(),
+            log: log.clone(),
+            stash: None,
+        },
+        &root,
+    );
+    drop(obj.clone());
+    assert!(log.borrow().is_empty());
+    drop(obj);
+    assert_eq!(*log.borrow(), ["finalize", "drop"]);
+    root.collect_cycles();
+    assert_eq!(log.borrow().len(), 2);
+}
+
+#[test]
+fn test_finalize_cycle() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let weak = make_finalized_cycle(&log, None, &root);
+    assert!(log.borrow().is_empty());
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 2);
+    assert_eq!(*log.borrow(), ["finalize", "finalize", "drop", "drop"]);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_finalize_resurrection() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let stash = Rc::new(RefCell::new(Vec::new()));
+    let weak = make_finalized_cycle(&log, Some(&stash), &root);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.white, 2);
+    assert_eq!(stats.freed, 0);
+    assert_eq!(*log.borrow(), ["finalize", "finalize"]);
+
+    // Back to a consistent live cycle, held from the stash.
+    let obj1 = weak.upgrade().unwrap();
+    assert_eq!(obj1.strong(), 2);
+    let obj2 = stash.borrow_mut().pop().unwrap();
+    assert_eq!(obj2.strong(), 2);
+    root.collect_cycles();
+    assert_eq!(log.borrow().len(), 2);
+
+    // Finalizers don't run a second time.
+    drop(obj1);
+    drop(obj2);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 2);
+    assert_eq!(*log.borrow(), ["finalize", "finalize", "drop", "drop"]);
+    assert!(weak.upgrade().is_none());
+}
+
+/// Any number of `Acc`s.
+enum Atomic {}
+
+impl Kind for Atomic {
+    type Edges = RwLock<Vec<Acc<Node<Atomic>>>>;
+}
+
+impl Node<Atomic> {
+    fn acc(drops: &Drops, root: &Arc<AtomicCycleCollector>) -> Acc<Node<Atomic>> {
+        Acc::new(Node::with(Vec::new().into(), drops), root)
+    }
+
+    fn link(&self, to: &Acc<Node<Atomic>>) {
+        self.edges.write().unwrap().push(to.clone());
+    }
+}
+
+#[test]
+fn test_atomic_acyclic() {
+    let root = Arc::new(AtomicCycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let node = Node::acc(&drops, &root);
+    node.link(&Node::acc(&drops, &root));
+    let clone = node.clone();
+    thread::spawn(move || drop(clone)).join().unwrap();
+    assert_eq!(node.strong(), 1);
+    drop(node);
+    assert_eq!(drops.get(), 2);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 2);
+    assert_eq!(stats.white, 0);
+}
+
+#[test]
+fn test_atomic_cycles_across_threads() {
+    let root = Arc::new(AtomicCycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let shared = Node::acc(&drops, &root);
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (root, drops, shared) = (root.clone(), drops.clone(), shared.clone());
+            thread::spawn(move || {
+                let _mutator = root.enter();
+                for _ in 0..100 {
+                    let a = Node::acc(&drops, &root);
+                    let b = Node::acc(&drops, &root);
+                    a.link(&b);
+                    b.link(&a);
+                    b.link(&shared);
+                    shared.link(&a);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    root.collect_cycles();
+    assert_eq!(drops.get(), 0);
+    drop(shared);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.white, 801);
+    assert_eq!(drops.get(), 801);
+}
+
+#[test]
+fn test_atomic_stop_the_world() {
+    let root = Arc::new(AtomicCycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mutator = {
+        let (root, drops, done) = (root.clone(), drops.clone(), done.clone());
+        thread::spawn(move || {
+            let mut mutator = root.enter();
+            let head = Node::acc(&drops, &root);
+            let mut rounds = 0;
+            while !done.load(Ordering::SeqCst) || rounds < 100 {
+                // Rotate a live cycle through head while collections run.
+                let next = Node::acc(&drops, &root);
+                next.link(&head);
+                let old = std::mem::take(&mut *head.edges.write().unwrap());
+                head.link(&next);
+                drop(old);
+                mutator.safepoint();
+                rounds += 1;
+            }
+            assert_eq!(head.edges.read().unwrap().len(), 1);
+            rounds
+        })
+    };
+    for _ in 0..20 {
+        let a = Node::acc(&drops, &root);
+        a.link(&a);
+        drop(a);
+        root.collect_cycles();
+    }
+    done.store(true, Ordering::SeqCst);
+    let rounds = mutator.join().unwrap();
+    // All but the last rotated node were released, leaving it and head as
+    // the only garbage cycle that's left.
+    assert_eq!(drops.get(), 20 + rounds - 1);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 20 + rounds + 1);
+    assert_eq!(root.stats().white, 22);
+}
+
+/// `Atomic` edges whose `trace` gives mutators time to get in its way.
+enum Slow {}
+
+struct Yielding<T>(T);
+
+unsafe impl<T: Trace> Trace for Yielding<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        thread::yield_now();
+        self.0.trace(tracer);
+    }
+}
+
+impl Kind for Slow {
+    type Edges = Yielding<RwLock<Vec<Acc<Node<Slow>>>>>;
+}
+
+#[test]
+fn test_atomic_mutation_outside_region() {
+    let root = Arc::new(AtomicCycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let collector = {
+        let (root, done) = (root.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                root.collect_cycles();
+            }
+        })
+    };
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (root, drops) = (root.clone(), drops.clone());
+            thread::spawn(move || {
+                let node = |edges| {
+                    let edges = Yielding(RwLock::new(edges));
+                    Acc::new(Node::<Slow>::with(edges, &drops), &root)
+                };
+                // No region: the edges change while collections trace them.
+                let head = node(Vec::new());
+                for _ in 0..2000 {
+                    let next = node(vec![head.clone()]);
+                    let mut edges = head.edges.0.write().unwrap();
+                    let old = std::mem::replace(&mut *edges, vec![next]);
+                    // Blocks on a running collection with the lock held.
+                    drop(old);
+                    drop(edges);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    collector.join().unwrap();
+    root.collect_cycles();
+    assert_eq!(drops.get(), 4 * 2001);
+}
+
+#[test]
+fn test_atomic_untraceable() {
+    let root = Arc::new(AtomicCycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let a = Node::acc(&drops, &root);
+    let b = Node::acc(&drops, &root);
+    a.link(&b);
+    b.link(&a);
+    drop(b);
+    let edges = a.edges.write().unwrap();
+    drop(a.clone());
+    let stats = thread::scope(|s| s.spawn(|| root.collect_cycles()).join().unwrap());
+    assert_eq!(stats.untraceable, 1);
+    assert_eq!(stats.freed, 0);
+    drop(edges);
+    drop(a);
+    assert_eq!(root.collect_cycles().freed, 2);
+    assert_eq!(drops.get(), 2);
+}
+
+#[derive(Trace)]
+#[trace(acyclic)]
+struct Num(RefCell<f64>);
+
+#[derive(Trace)]
+struct NumHolder {
+    to: RefCell<Option<Cc<NumHolder>>>,
+    num: Cc<Num>,
+}
+
+#[test]
+fn test_acyclic_green() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let int = Cc::new(5i32, &root);
+    let strings = Cc::new(vec![Some(String::from("a"))], &root);
+    let num = Cc::new(Num(1.0.into()), &root);
+    let cell = Cc::new(RefCell::new(None::<Cc<i32>>), &root);
+    let pair = Cc::new((1i32, String::from("b")), &root);
+    let boxed = Cc::new(Box::new(1u8), &root);
+    let int_cell = Cc::new(RefCell::new(1), &root);
+    for green in [
+        int.color(),
+        strings.color(),
+        num.color(),
+        boxed.color(),
+        int_cell.color(),
+    ] {
+        assert_eq!(green, Color::Green);
+    }
+    assert_eq!(cell.color(), Color::Black);
+    // Tuples may end in an unsized value, so they can't ask their elements.
+    assert_eq!(pair.color(), Color::Black);
+    drop(int.clone());
+    drop(strings.clone());
+    drop(num.clone());
+    assert_eq!(int.color(), Color::Green);
+    assert_eq!(root.collect_cycles().roots, 0);
+    drop(cell.clone());
+    assert_eq!(root.collect_cycles().roots, 1);
+}
+
+#[test]
+fn test_cycle_owning_green() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let num = Cc::new(Num(1.0.into()), &root);
+    let obj1 = Cc::new(
+        NumHolder {
+            to: None.into(),
+            num: num.clone(),
+        },
+        &root,
+    );
+    let obj2 = Cc::new(
+        NumHolder {
+            to: Some(obj1.clone()).into(),
+            num: num.clone(),
+        },
+        &root,
+    );
+    *obj1.to.borrow_mut() = Some(obj2);
+    drop(obj1);
+    assert_eq!(num.strong(), 3);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.white, 2);
+    assert_eq!(stats.marked_gray, 2);
+    assert_eq!(num.strong(), 1);
+    *num.0.borrow_mut() += 1.0;
+    assert_eq!(num.color(), Color::Green);
+}
+
+#[test]
+fn test_metadata_size() {
+    // The vtable pointer and three `u32`s.
+    assert_eq!(std::mem::size_of::<CcBoxMetaData>(), 24);
+    assert_eq!(std::mem::size_of::<CcPtr>(), std::mem::size_of::<usize>());
+    assert_eq!(std::mem::size_of::<Cc<String>>(), std::mem::size_of::<usize>());
+}
+
+#[test]
+fn test_thin_pointers() {
+    let word = std::mem::size_of::<usize>();
+    assert_eq!(std::mem::size_of::<Cc<u8>>(), word);
+    assert_eq!(std::mem::size_of::<Cc<Node<Local>>>(), word);
+    assert_eq!(std::mem::size_of::<Option<Cc<Node<Local>>>>(), word);
+    assert_eq!(std::mem::size_of::<Weak<Node<Local>>>(), word);
+
+    // Whatever the payload, tracing reports the thin pointer to the header.
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let node = Node::new(&drops, &root);
+    node.link(&node);
+    let slice_node = Cc::new(Node::<Slice>::with(None.into(), &drops), &root);
+    let slice = Cc::<[_]>::from_iter([slice_node.clone()], &root);
+    *slice_node.edges.borrow_mut() = Some(slice.clone());
+    let dyn_node = Cc::new(Node::<Dyn>::with(None.into(), &drops), &root);
+    let named: Cc<dyn Named> = cc_coerce!(dyn_node.clone() => dyn Named);
+    *dyn_node.edges.borrow_mut() = Some(named.clone());
+
+    fn traced<T: Trace + ?Sized>(value: &T) -> Vec<CcPtr> {
+        let mut edges = Vec::new();
+        value.trace(&mut |to| edges.push(to.get_ptr()));
+        edges
+    }
+    assert_eq!(traced(&*node), [node.get_ptr()]);
+    assert_eq!(traced(&*slice_node), [slice.get_ptr()]);
+    assert_eq!(traced(&*slice), [slice_node.get_ptr()]);
+    assert_eq!(named.get_ptr(), dyn_node.get_ptr());
+    assert_eq!(traced(&*named), [dyn_node.get_ptr()]);
+
+    drop((node, slice_node, slice, dyn_node, named));
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 4);
+    assert_eq!(drops.get(), 3);
+    assert_eq!(root.verify(), vec![]);
+}
+
+#[test]
+fn test_strong_overflow() {
+    let root = Arc::new(CycleCollector::new());
+    let five = Cc::new(5i32, &root);
+    let limit = header::STRONG_OVERFLOW as usize;
+    five.metadata().set_strong(limit - 1);
+    let clones = [five.clone(), five.clone(), five.clone()];
+    assert_eq!(five.strong(), limit + 2);
+    assert_eq!(five.color(), Color::Green);
+    assert_eq!(root.overflow.borrow().len(), 1);
+    drop(clones);
+    assert_eq!(five.strong(), limit - 1);
+    assert!(root.overflow.borrow().is_empty());
+    five.metadata().set_strong(1);
+    assert_eq!(*five, 5);
+}
+
+#[test]
+fn test_collector_dropped_with_its_boxes() {
+    let root = Arc::new(CycleCollector::new());
+    let collector = Arc::downgrade(&root);
+    let five = Cc::new(5i32, &root);
+    let weak = five.downgrade();
+    drop(root);
+    assert!(collector.upgrade().is_some());
+    drop(five);
+    // The box is only freed once the weak pointer goes too.
+    assert!(collector.upgrade().is_some());
+    drop(weak);
+    assert!(collector.upgrade().is_none());
+
+    // Freed by an explicit collection, the collector stays registered until
+    // the next one is.
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let collector = Arc::downgrade(&root);
+    let drops = Drops::default();
+    make_garbage_cycle(&drops, &root);
+    root.collect_cycles();
+    assert_eq!(drops.get(), 2);
+    drop(root);
+    let other = Arc::new(CycleCollector::new());
+    let _five = Cc::new(5i32, &other);
+    assert!(collector.upgrade().is_none());
+}
+
+#[test]
+fn test_unregister_twice() {
+    let root = Arc::new(CycleCollector::new());
+    let five = Cc::new(5i32, &root);
+    let id = five.metadata().root;
+    let weak = five.downgrade();
+    drop(five);
+    drop(weak);
+    // As when done with another handle right after.
+    id.unregister_if_unused();
+    drop(root);
+
+    // Each gets a slot of its own.
+    let (root1, root2) = (Arc::new(CycleCollector::new()), Arc::new(CycleCollector::new()));
+    let one = Cc::new(1i32, &root1);
+    let two = Cc::new(2i32, &root2);
+    assert_ne!(one.metadata().root, two.metadata().root);
+    assert!(std::ptr::eq(one.metadata().root(), &*root1));
+}
+
+/// A `Weak` to itself.
+enum SelfRef {}
+
+impl Kind for SelfRef {
+    type Edges = Weak<Node<SelfRef>>;
 }
 
 #[test]
-fn test_simple_ref_cycle(){
+fn test_new_cyclic() {
     let root = Arc::new(CycleCollector::new());
-    // let _five = Cc::new(5i32, &root);
-    let obj1 = Cc::new(TestObj{to:None.into()}, &root);
-    let obj2 = Cc::new(TestObj{to:None.into()}, &root);
+    let drops = Drops::default();
+    let obj = Cc::new_cyclic(
+        |me| {
+            assert!(me.upgrade().is_none());
+            Node::<SelfRef>::with(me.clone(), &drops)
+        },
+        &root,
+    );
+    assert_eq!(obj.strong(), 1);
+    assert_eq!(obj.weak(), 2);
+    let again = obj.edges.upgrade().unwrap();
+    assert!(std::ptr::eq(&*again, &*obj));
+    drop(again);
+    drop(obj);
+    assert_eq!(drops.get(), 1);
+    // Frees the box, buffered when `again` was dropped.
+    root.collect_cycles();
+
+    // A panic while building frees the box.
+    let collector = Arc::downgrade(&root);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        Cc::<Node<SelfRef>>::new_cyclic(|_| panic!("no value"), &root)
+    }));
+    assert!(result.is_err());
+    drop(root);
+    assert!(collector.upgrade().is_none());
+}
+
+#[test]
+fn test_cc_unwrap_and_mut() {
+    let root = Arc::new(CycleCollector::new());
+    let mut five = Cc::new(5i32, &root);
+    let other = five.clone();
+    assert!(Cc::ptr_eq(&five, &other));
+    assert_eq!(Cc::strong_count(&five), 2);
+    assert!(Cc::get_mut(&mut five).is_none());
+    // Shared with `other`, so the value is cloned.
+    *Cc::make_mut(&mut five) += 1;
+    assert!(!Cc::ptr_eq(&five, &other));
+    assert_eq!((*five, *other), (6, 5));
+    *Cc::get_mut(&mut five).unwrap() += 1;
+
+    // Only weakly shared, so the value is moved.
+    let weak = five.downgrade();
+    assert_eq!(Cc::weak_count(&five), 1);
+    *Cc::make_mut(&mut five) += 1;
+    assert_eq!(*five, 8);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(weak.strong_count(), 0);
+
+    let again = other.clone();
+    let other = Cc::try_unwrap(other).unwrap_err();
+    assert_eq!(Cc::into_inner(again), None);
+    assert_eq!(Cc::try_unwrap(other), Ok(5));
+    assert_eq!(Cc::into_inner(five), Some(8));
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let obj = Finalized {
+        to: None.into(),
+        log: log.clone(),
+        stash: None,
+    };
+    let obj = Cc::into_inner(Cc::new_finalizable(obj, &root)).unwrap();
+    drop(obj);
+    assert_eq!(*log.borrow(), ["drop"]);
+}
+
+#[test]
+fn test_cc_forwarding_impls() {
+    let root = Arc::new(CycleCollector::new());
+    let a = Cc::new(String::from("a"), &root);
+    let b = Cc::new(String::from("b"), &root);
+    assert_eq!(a, Cc::new(String::from("a"), &root));
+    assert!(a < b);
+    assert_eq!(a.cmp(&b), std::cmp::Ordering::Less);
+    assert_eq!(format!("{a} {b:?}"), "a \"b\"");
+    assert_eq!(format!("{a:p}"), format!("{:p}", &*a));
+    let set: std::collections::HashSet<Cc<String>> = [a.clone(), b].into_iter().collect();
+    assert!(set.contains(&String::from("a")));
+    let s: &str = a.as_ref();
+    assert_eq!(s, "a");
+
+    let empty = Weak::<String>::new();
+    assert!(empty.upgrade().is_none());
+    assert_eq!(empty.strong_count(), 0);
+    assert!(empty.ptr_eq(&Weak::default()));
+    assert!(!empty.ptr_eq(&a.downgrade()));
+    assert!(a.downgrade().ptr_eq(&a.downgrade()));
+    drop(empty.clone());
+}
+
+/// A `Cc` to a slice of nodes.
+enum Slice {}
+
+impl Kind for Slice {
+    type Edges = RefCell<Option<Cc<[Cc<Node<Slice>>]>>>;
+}
+
+#[test]
+fn test_unsized_slices() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let strings = Cc::<[String]>::from_iter((0..3).map(|i| i.to_string()), &root);
+    assert_eq!(&*strings, ["0", "1", "2"]);
+    assert_eq!(strings.color(), Color::Green);
+    let empty = Cc::<[u64]>::from_box(Box::new([]), &root);
+    assert!(empty.is_empty());
+    let hello = Cc::<str>::from_str_in("hello", &root);
+    assert_eq!(format!("{hello}"), "hello");
+    assert_eq!(&*Cc::<str>::from_box("world".into(), &root), "world");
+
+    let drops = Drops::default();
+    let node = Cc::new(Node::<Slice>::with(None.into(), &drops), &root);
+    let slice = Cc::<[_]>::from_iter([node.clone(), node.clone()], &root);
+    *node.edges.borrow_mut() = Some(slice);
+    drop(node);
+    let stats = root.collect_cycles();
+    assert_eq!(drops.get(), 1);
+    assert_eq!(stats.freed, 2);
+    assert_eq!(
+        stats.bytes_freed,
+        std::mem::size_of::<CcBox<Node<Slice>>>() + 24 + 2 * std::mem::size_of::<usize>()
+    );
+}
+
+trait Named: Trace {
+    fn name(&self) -> &'static str;
+}
+
+/// A `Cc` to a trait object.
+enum Dyn {}
+
+impl Kind for Dyn {
+    type Edges = RefCell<Option<Cc<dyn Named>>>;
+}
+
+impl Named for Node<Dyn> {
+    fn name(&self) -> &'static str {
+        "node"
+    }
+}
+
+#[test]
+fn test_cc_coerce() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let node = Cc::new(Node::<Dyn>::with(None.into(), &drops), &root);
+    let named: Cc<dyn Named> = cc_coerce!(node.clone() => dyn Named);
+    assert_eq!(named.name(), "node");
+    assert!(std::ptr::addr_eq(&*named, &*node));
+    assert_eq!(Cc::strong_count(&node), 2);
+    *node.edges.borrow_mut() = Some(named);
+    drop(node);
+    assert_eq!(root.collect_cycles().freed, 1);
+    assert_eq!(drops.get(), 1);
+}
+
+#[derive(Trace)]
+struct AnyNode {
+    to: RefCell<Option<Cc<dyn CcAny>>>,
+}
+
+#[test]
+fn test_downcast() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let heap: Vec<Cc<dyn CcAny>> = vec![
+        cc_coerce!(Cc::new(5i32, &root) => dyn CcAny),
+        cc_coerce!(Cc::new(String::from("five"), &root) => dyn CcAny),
+    ];
+    assert!(heap[0].is::<i32>());
+    assert!(!heap[0].is::<u32>());
+    let weak = heap[1].downgrade();
+    let five = heap[0].clone().downcast::<i32>().unwrap();
+    assert_eq!(*five, 5);
+    let string = heap[1].clone().downcast::<i32>().unwrap_err();
+    assert_eq!(*string.downcast::<String>().unwrap(), "five");
+
+    drop(heap);
+    assert!(weak.is::<String>());
+    let weak = weak.downcast::<i32>().unwrap_err();
+    let weak = weak.downcast::<String>().unwrap();
+    assert!(weak.upgrade().is_none());
+
+    let node = Cc::new(AnyNode { to: None.into() }, &root);
+    *node.to.borrow_mut() = Some(cc_coerce!(node.clone() => dyn CcAny));
+    let weak = node.downgrade();
+    drop(node);
+    assert_eq!(root.collect_cycles().freed, 1);
+    assert!(weak.upgrade().is_none());
+}
+
+/// Collects from its `Drop` impl, and makes more garbage there.
+struct Reentrant {
+    to: RefCell<Option<Cc<Reentrant>>>,
+    root: RootsRef,
+    drops: Drops,
+    /// Dropped by `trace`, like a cache being cleared.
+    cache: RefCell<Option<Cc<Node<Local>>>>,
+}
+
+unsafe impl Trace for Reentrant {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.cache.borrow_mut().take();
+        if let Some(to) = self.to.borrow().as_ref() {
+            to.trace(tracer)
+        }
+    }
+}
+
+impl Drop for Reentrant {
+    fn drop(&mut self) {
+        make_garbage_cycle(&self.drops, &self.root);
+        self.root.collect_cycles();
+    }
+}
+
+fn make_reentrant(drops: &Drops, root: &RootsRef) -> Cc<Reentrant> {
+    Cc::new(
+        Reentrant {
+            to: None.into(),
+            root: root.clone(),
+            drops: drops.clone(),
+            cache: Some(Node::new(drops, root)).into(),
+        },
+        root,
+    )
+}
+
+#[test]
+fn test_collect_from_drop() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let obj1 = make_reentrant(&drops, &root);
+    let obj2 = make_reentrant(&drops, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1);
+    drop(obj2);
+    let stats = root.collect_cycles();
+    // The cycles made while dropping it are collected by a second pass.
+    assert_eq!(stats.collections, 2);
+    assert_eq!(drops.get(), 2 + 2 * 2);
+    assert!(root.roots.borrow().is_empty());
+
+    // Released outside of a collection.
+    let obj = make_reentrant(&drops, &root);
+    drop(obj);
+    assert_eq!(root.stats().collections, 3);
+    assert_eq!(drops.get(), 7 + 2);
+}
+
+#[test]
+fn test_drop_while_tracing() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let obj1 = make_reentrant(&drops, &root);
+    let obj2 = make_reentrant(&drops, &root);
     *obj1.to.borrow_mut() = Some(obj2.clone());
     *obj2.to.borrow_mut() = Some(obj1.clone());
-    dbg!("Cycle made");
-    dbg!("dropping obj2 now");
+    // Still alive, so only the caches go.
+    drop(obj2);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 0);
+    assert_eq!(drops.get(), 2);
+    assert!(obj1.cache.borrow().is_none());
+    assert_eq!(Cc::strong_count(&obj1), 2);
+}
+
+#[derive(Trace)]
+struct Bomb {
+    to: RefCell<Option<Cc<Bomb>>>,
+}
+
+impl Drop for Bomb {
+    fn drop(&mut self) {
+        panic!("boom");
+    }
+}
+
+#[test]
+fn test_panic_in_drop() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let bomb = || Cc::new(Bomb { to: None.into() }, &root);
+    let obj1 = bomb();
+    *obj1.to.borrow_mut() = Some(bomb());
+    let obj2 = obj1.to.borrow().clone().unwrap();
+    *obj2.to.borrow_mut() = Some(obj1);
+    drop(obj2);
+    let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| root.collect_cycles()));
+    assert!(caught.is_err());
+    // The next collection still runs.
+    make_garbage_cycle(&drops, &root);
+    assert_eq!(root.collect_cycles().freed, 2);
+    assert_eq!(drops.get(), 2);
+
+    let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(bomb())));
+    assert!(caught.is_err());
+    // And so do releases.
+    drop(Node::new(&drops, &root));
+    assert_eq!(drops.get(), 3);
+}
+
+#[derive(Trace)]
+struct CellNode {
+    to: RefCell<Option<Cc<CellNode>>>,
+}
+
+#[test]
+fn test_trace_borrowed_refcell() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let obj1 = Cc::new(
+        CellNode {
+            to: RefCell::new(None),
+        },
+        &root,
+    );
+    let obj2 = Cc::new(
+        CellNode {
+            to: RefCell::new(Some(obj1.clone())),
+        },
+        &root,
+    );
+    *obj1.to.borrow_mut() = Some(obj2.clone());
     drop(obj2);
-    dbg!("obj2 dropped");
-    dbg!("dropping obj1 now");
+
+    // Collecting while the mutator holds on to `obj1.to` keeps the cycle.
+    let to = obj1.to.borrow_mut();
+    let stats = root.collect_cycles();
+    assert_eq!(stats.untraceable, 1);
+    assert_eq!(stats.freed, 0);
+    let obj2 = to.as_ref().unwrap();
+    assert_eq!(Cc::strong_count(obj2), 1);
+    assert!(obj2.to.borrow().is_some());
+    drop(to);
+
     drop(obj1);
-    dbg!("obj1 dropped");
-    //*cycle.to.borrow_mut() = Some(cycle.clone());
-    //drop(cycle);
-    //root.collect_cycles();
-    dbg!(root);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.untraceable, 0);
+    assert_eq!(stats.freed, 2);
+
+    // The same when the collection is made to run by a drop.
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::RootBuffer(1)));
+    let obj = Cc::new(
+        CellNode {
+            to: RefCell::new(None),
+        },
+        &root,
+    );
+    *obj.to.borrow_mut() = Some(obj.clone());
+    let other = obj.clone();
+    let mut to = obj.to.borrow_mut();
+    drop(other);
+    *to = None;
+    drop(to);
+    // Once for each of the two drops.
+    assert_eq!(root.stats().untraceable, 2);
+    assert_eq!(Cc::strong_count(&obj), 1);
+}
+
+#[derive(Trace)]
+struct LockNode {
+    mutex: Mutex<Option<Cc<LockNode>>>,
+    rwlock: RwLock<Option<Cc<LockNode>>>,
+}
+
+#[test]
+fn test_trace_locks() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let new = || {
+        Cc::new(
+            LockNode {
+                mutex: Mutex::new(None),
+                rwlock: RwLock::new(None),
+            },
+            &root,
+        )
+    };
+    let obj1 = new();
+    let obj2 = new();
+    *obj1.mutex.lock().unwrap() = Some(obj2.clone());
+    *obj2.rwlock.write().unwrap() = Some(obj1.clone());
+
+    // Locked by this thread: kept without blocking.
+    let mutex = obj1.mutex.lock().unwrap();
+    let rwlock = mutex.as_ref().unwrap().rwlock.write().unwrap();
+    drop(obj2);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.untraceable, 1);
+    assert_eq!(stats.freed, 0);
+    drop(rwlock);
+    drop(mutex);
+
+    // Reading is fine.
+    let obj2 = obj1.mutex.lock().unwrap().clone().unwrap();
+    let rwlock = obj2.rwlock.read().unwrap();
+    drop(obj1);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.untraceable, 0);
+    assert_eq!(stats.freed, 0);
+    drop(rwlock);
+
+    // The cycle through both is garbage once unlocked.
+    drop(obj2);
+    let stats = root.collect_cycles();
+    assert_eq!(stats.freed, 2);
+}
+
+#[test]
+fn test_std_trace_impls() {
+    use std::borrow::Cow;
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    use std::mem::ManuallyDrop;
+    use std::num::Wrapping;
+
+    fn count<T: Trace>(value: &T) -> usize {
+        let mut count = 0;
+        value.trace(&mut |_| count += 1);
+        count
+    }
+
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let cc = Cc::new(5i32, &root);
+    assert_eq!(count(&[cc.clone(), cc.clone(), cc.clone()]), 3);
+    let mut manual = ManuallyDrop::new(cc.clone());
+    assert_eq!(count(&manual), 1);
+    unsafe { ManuallyDrop::drop(&mut manual) };
+    assert_eq!(count(&Cell::new(Some(cc.clone()))), 1);
+    assert_eq!(count(&Reverse(cc.clone())), 1);
+    assert_eq!(count(&Wrapping(cc.clone())), 1);
+    assert_eq!(count(&(cc.clone()..cc.clone())), 2);
+    assert_eq!(count(&(cc.clone()..=cc.clone())), 2);
+    assert_eq!(count(&BinaryHeap::from([cc.clone(), cc.clone()])), 2);
+    let borrowed = [cc.clone()];
+    // Owned by `borrowed`, not by the `Cow`.
+    assert_eq!(count(&Cow::Borrowed(&borrowed[..])), 0);
+    assert_eq!(count(&&borrowed), 0);
+    drop(borrowed);
+    assert_eq!(count(&Cow::<[Cc<i32>]>::Owned(vec![cc.clone()])), 1);
+    assert_eq!(count(&Box::pin(cc.clone())), 1);
+    assert_eq!(
+        count(&(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, cc.clone())),
+        1
+    );
+
+    assert!(<[u8; 4]>::is_acyclic());
+    assert!(!<[Cc<i32>; 4]>::is_acyclic());
+    assert!(Cell::<String>::is_acyclic());
+    assert!(std::marker::PhantomData::<Cc<i32>>::is_acyclic());
+    assert!(std::time::Duration::is_acyclic());
+    assert!(std::num::NonZeroU32::is_acyclic());
+    assert!(AtomicUsize::is_acyclic());
+    assert!(<fn(i32) -> Cc<i32>>::is_acyclic());
+    assert!(std::net::SocketAddr::is_acyclic());
+    // Tuples may end in an unsized value, so they can't ask their elements.
+    assert!(!<(i32, String)>::is_acyclic());
+    assert!(!<(i32, Cc<i32>)>::is_acyclic());
+    fn is_trace<T: Trace + ?Sized>() {}
+    is_trace::<(i32, [Cc<i32>])>();
+    assert!(Box::<[u8]>::is_acyclic());
+    assert!(Box::<str>::is_acyclic());
+    assert!(!Box::<dyn Trace>::is_acyclic());
+
+    // Arrays coerce to slices.
+    let array = Cc::new([cc.clone(), cc.clone()], &root);
+    let slice: Cc<[Cc<i32>]> = cc_coerce!(array => [Cc<i32>]);
+    assert_eq!(slice.len(), 2);
+    assert_eq!(Cc::strong_count(&cc), 3);
+    drop(slice);
+    assert_eq!(Cc::strong_count(&cc), 1);
+}
+
+/// Reports `to` as many times as `times` says, which breaks `Trace`'s
+/// contract unless it is 1.
+struct BadTrace {
+    to: RefCell<Option<Cc<BadTrace>>>,
+    times: Cell<usize>,
+    flip: bool,
+}
+
+unsafe impl Trace for BadTrace {
+    fn trace(&self, tracer: &mut Tracer) {
+        for _ in 0..self.times.get() {
+            self.to.trace(tracer);
+        }
+        if self.flip {
+            self.times.set(1 - self.times.get());
+        }
+    }
+}
+
+fn make_bad(times: usize, flip: bool, root: &RootsRef) -> Cc<BadTrace> {
+    Cc::new(
+        BadTrace {
+            to: None.into(),
+            times: times.into(),
+            flip,
+        },
+        root,
+    )
+}
+
+#[test]
+fn test_checked_mode() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    root.set_checked(true);
+
+    // Sound impls pass and get collected as usual.
+    let obj1 = make_bad(1, false, &root);
+    let obj2 = make_bad(1, false, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    drop(obj1);
+    assert_eq!(root.check_traces(), Ok(()));
+    drop(obj2);
+    assert_eq!(root.collect_cycles().freed, 2);
+
+    // Reported twice with a single reference.
+    let obj1 = make_bad(2, false, &root);
+    let obj2 = make_bad(1, false, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    drop(obj2);
+    let ptr = obj1.to.borrow().as_ref().unwrap().get_ptr();
+    match root.check_traces() {
+        Err(TraceError::OverReported {
+            type_name,
+            ptr: at,
+            reported: 2,
+            strong: 1,
+            reported_by,
+        }) => {
+            assert!(type_name.ends_with("BadTrace"));
+            assert_eq!(at, ptr);
+            assert_eq!(reported_by.len(), 1);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| root.collect_cycles()))
+        .unwrap_err();
+    let msg = err.downcast_ref::<String>().unwrap();
+    assert!(msg.contains("traced 2 times with a strong count of 1"), "{}", msg);
+    // Nothing was touched.
+    assert_eq!(Cc::strong_count(&obj1), 2);
+    assert_eq!(Cc::strong_count(obj1.to.borrow().as_ref().unwrap()), 1);
+    *obj1.to.borrow_mut() = None;
+    drop(obj1);
+    // Released, and only left in the roots buffer.
+    assert_eq!(root.collect_cycles().freed, 2);
+
+    // Reports its edge every other call.
+    let obj1 = make_bad(1, true, &root);
+    let obj2 = make_bad(1, false, &root);
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    *obj2.to.borrow_mut() = Some(obj1.clone());
+    drop(obj1);
+    match root.check_traces() {
+        Err(TraceError::Nondeterministic { type_name, .. }) => {
+            assert!(type_name.ends_with("BadTrace"))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    obj2.to.borrow_mut().take();
+}
+
+#[test]
+fn test_verify() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let obj1 = Cc::new(
+        TestObj {
+            to: RefCell::new(None),
+        },
+        &root,
+    );
+    let obj2 = Cc::new(
+        TestObj {
+            to: RefCell::new(Some(obj1.clone())),
+        },
+        &root,
+    );
+    *obj1.to.borrow_mut() = Some(obj2.clone());
+    drop(obj2.clone());
+    assert_eq!(root.verify(), vec![]);
+
+    // Break the invariants by hand, one box at a time.
+    let kinds = || {
+        root.verify()
+            .into_iter()
+            .map(|v| (v.ptr, v.kind))
+            .collect::<Vec<_>>()
+    };
+    let m1 = obj1.metadata();
+    let m2 = obj2.metadata();
+    m1.set_buffered(true);
+    assert_eq!(
+        kinds(),
+        vec![(obj1.get_ptr(), ViolationKind::BufferedNotInRoots)]
+    );
+    m1.set_buffered(false);
+    m2.set_buffered(false);
+    m1.set_color(Color::Gray);
+    assert_eq!(
+        kinds(),
+        vec![
+            (obj2.get_ptr(), ViolationKind::InRootsNotBuffered),
+            (obj1.get_ptr(), ViolationKind::Color(Color::Gray)),
+        ]
+    );
+    m2.set_buffered(true);
+    m1.set_color(Color::Black);
+    m1.set_weak(0);
+    assert_eq!(
+        kinds(),
+        vec![(obj1.get_ptr(), ViolationKind::NoWeakWhileStrong)]
+    );
+    m1.set_weak(1);
+    let strong = m1.strong();
+    m1.set_strong(0);
+    let violations = root.verify();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].ptr, obj1.get_ptr());
+    assert_eq!(
+        violations[0].kind,
+        ViolationKind::ReleasedButReachable {
+            from: obj2.get_ptr()
+        }
+    );
+    assert!(violations[0].type_name.ends_with("TestObj"));
+    m1.set_strong(strong);
+    assert_eq!(root.verify(), vec![]);
+
+    drop(obj1);
+    drop(obj2);
+    root.set_checked(true);
+    assert_eq!(root.collect_cycles().freed, 2);
+    assert_eq!(root.verify(), vec![]);
+}
+
+/// Nodes of the random heaps built by `run_ops`, numbered to match the
+/// model.
+enum Fuzz {}
+
+#[derive(Trace)]
+struct Numbered {
+    #[trace(skip)]
+    id: usize,
+    to: RefCell<Vec<Cc<Node<Fuzz>>>>,
+}
+
+impl Kind for Fuzz {
+    type Edges = Numbered;
+}
+
+/// One step of a random mutator. Indices are taken modulo the length of the
+/// list they pick from, and a step with nothing to pick does nothing, so
+/// that any subsequence of a valid program is one too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Alloc,
+    /// Make the node of one handle point to the node of another.
+    Link(usize, usize),
+    /// Remove one of the edges of the node of a handle.
+    Unlink(usize, usize),
+    Clone(usize),
+    Downgrade(usize),
+    /// Upgrade a weak pointer, keeping the result as a handle.
+    Upgrade(usize),
+    Drop(usize),
+    Collect,
+}
+
+/// Run `ops` on a fresh collector, checking it against a model of the heap
+/// after every step: a node must never be dropped while reachable from a
+/// handle, every handle's strong count must match the model, the heap must
+/// verify, and after `Collect` every unreachable node must be gone.
+fn run_ops(ops: &[Op], policy: CollectionPolicy) -> Result<(), String> {
+    let root = Arc::new(CycleCollector::with_policy(policy));
+    root.set_checked(true);
+    // The drops of every node, by id.
+    let mut drops: Vec<Drops> = Vec::new();
+    let mut handles: Vec<Cc<Node<Fuzz>>> = Vec::new();
+    // With the id of the node each points to.
+    let mut weaks: Vec<(Weak<Node<Fuzz>>, usize)> = Vec::new();
+    // The model: the edges of every node, by id.
+    let mut edges: Vec<Vec<usize>> = Vec::new();
+
+    for (step, &op) in ops.iter().enumerate() {
+        let fail = |msg: String| Err(format!("step {} ({:?}): {}", step, op, msg));
+        match op {
+            Op::Alloc => {
+                let numbered = Numbered {
+                    id: edges.len(),
+                    to: Vec::new().into(),
+                };
+                drops.push(Drops::default());
+                edges.push(Vec::new());
+                let node = Node::<Fuzz>::with(numbered, drops.last().unwrap());
+                handles.push(Cc::new(node, &root));
+            }
+            Op::Link(from, to) if !handles.is_empty() => {
+                let from = &handles[from % handles.len()];
+                let to = handles[to % handles.len()].clone();
+                edges[from.edges.id].push(to.edges.id);
+                from.edges.to.borrow_mut().push(to);
+            }
+            Op::Unlink(of, i) if !handles.is_empty() => {
+                let of = &handles[of % handles.len()];
+                let id = of.edges.id;
+                if !edges[id].is_empty() {
+                    let i = i % edges[id].len();
+                    edges[id].remove(i);
+                    let removed = of.edges.to.borrow_mut().remove(i);
+                    drop(removed);
+                }
+            }
+            Op::Clone(h) if !handles.is_empty() => {
+                handles.push(handles[h % handles.len()].clone());
+            }
+            Op::Downgrade(h) if !handles.is_empty() => {
+                let h = &handles[h % handles.len()];
+                weaks.push((h.downgrade(), h.edges.id));
+            }
+            Op::Upgrade(w) if !weaks.is_empty() => {
+                let (weak, id) = &weaks[w % weaks.len()];
+                let is_dropped = drops[*id].get() > 0;
+                match weak.upgrade() {
+                    Some(_) if is_dropped => return fail(format!("upgraded dropped node {}", id)),
+                    Some(cc) => handles.push(cc),
+                    None if !is_dropped => return fail(format!("couldn't upgrade node {}", id)),
+                    None => {}
+                }
+            }
+            Op::Drop(h) if !handles.is_empty() => {
+                let len = handles.len();
+                drop(handles.swap_remove(h % len));
+            }
+            Op::Collect => {
+                root.collect_cycles();
+            }
+            _ => {}
+        }
+
+        let dropped: Vec<bool> = drops.iter().map(|drops| drops.get() > 0).collect();
+        let mut reachable = vec![false; edges.len()];
+        let mut stack: Vec<usize> = handles.iter().map(|h| h.edges.id).collect();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reachable[id], true) {
+                stack.extend(&edges[id]);
+            }
+        }
+        for (id, (&reachable, &dropped)) in reachable.iter().zip(dropped.iter()).enumerate() {
+            if reachable && dropped {
+                return fail(format!("reachable node {} was dropped", id));
+            }
+            if op == Op::Collect && !reachable && !dropped {
+                return fail(format!("unreachable node {} survived a collection", id));
+            }
+        }
+        for h in &handles {
+            let id = h.edges.id;
+            let expected = handles.iter().filter(|o| o.edges.id == id).count()
+                + (0..edges.len())
+                    .filter(|&from| !dropped[from])
+                    .map(|from| edges[from].iter().filter(|&&to| to == id).count())
+                    .sum::<usize>();
+            if Cc::strong_count(h) != expected {
+                return fail(format!(
+                    "node {} has a strong count of {}, expected {}",
+                    id,
+                    Cc::strong_count(h),
+                    expected
+                ));
+            }
+        }
+        let violations = root.verify();
+        if !violations.is_empty() {
+            return fail(format!("{:?}", violations));
+        }
+    }
+    Ok(())
+}
+
+/// `run_ops`, with a panic counted as a failure.
+fn run_ops_caught(ops: &[Op], policy: CollectionPolicy) -> Result<(), String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_ops(ops, policy)))
+        .unwrap_or_else(|err| {
+            let msg = err
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()));
+            Err(format!("panicked: {}", msg.unwrap_or_default()))
+        })
+}
+
+/// A xorshift generator, so that every case can be replayed from its seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+fn random_ops(seed: u64, len: usize) -> Vec<Op> {
+    let mut rng = XorShift(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    (0..len)
+        .map(|_| {
+            let (a, b) = (rng.next() % 16, rng.next() % 16);
+            match rng.next() % 16 {
+                0..=2 => Op::Alloc,
+                3..=6 => Op::Link(a, b),
+                7 => Op::Unlink(a, b),
+                8 => Op::Clone(a),
+                9 => Op::Downgrade(a),
+                10 => Op::Upgrade(a),
+                11..=13 => Op::Drop(a),
+                _ => Op::Collect,
+            }
+        })
+        .collect()
+}
+
+/// Shrink the failing `ops` to a locally minimal program that still fails:
+/// drop chunks of steps, halving the chunk size down to single steps, then
+/// make the remaining indices as small as possible.
+fn shrink(mut ops: Vec<Op>, fails: impl Fn(&[Op]) -> bool) -> Vec<Op> {
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.drain(start..(start + chunk).min(ops.len()));
+            if fails(&candidate) {
+                ops = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    for i in 0..ops.len() {
+        // Zero one index at a time, starting from the current step each time.
+        for arg in 0..2 {
+            let simpler = match (ops[i], arg) {
+                (Op::Link(_, b), 0) => Op::Link(0, b),
+                (Op::Link(a, _), 1) => Op::Link(a, 0),
+                (Op::Unlink(_, b), 0) => Op::Unlink(0, b),
+                (Op::Unlink(a, _), 1) => Op::Unlink(a, 0),
+                (Op::Clone(_), 0) => Op::Clone(0),
+                (Op::Downgrade(_), 0) => Op::Downgrade(0),
+                (Op::Upgrade(_), 0) => Op::Upgrade(0),
+                (Op::Drop(_), 0) => Op::Drop(0),
+                (op, _) => op,
+            };
+            let mut candidate = ops.clone();
+            candidate[i] = simpler;
+            if candidate != ops && fails(&candidate) {
+                ops = candidate;
+            }
+        }
+    }
+    ops
+}
+
+#[test]
+fn test_random_programs() {
+    let policies = [
+        CollectionPolicy::Manual,
+        CollectionPolicy::RootBuffer(2),
+        CollectionPolicy::Allocations(3),
+    ];
+    for seed in 0..300 {
+        let policy = policies[seed as usize % policies.len()];
+        let ops = random_ops(seed, 80);
+        if let Err(err) = run_ops_caught(&ops, policy) {
+            let ops = shrink(ops, |ops| run_ops_caught(ops, policy).is_err());
+            panic!(
+                "seed {} with {:?} failed: {}\nminimal program: {:?}\nfails with: {}",
+                seed,
+                policy,
+                err,
+                ops,
+                run_ops_caught(&ops, policy).unwrap_err(),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_shrink() {
+    // Fails whenever a `Link` comes after two `Alloc`s.
+    let fails = |ops: &[Op]| {
+        let mut allocs = 0;
+        ops.iter().any(|op| match op {
+            Op::Alloc => {
+                allocs += 1;
+                false
+            }
+            Op::Link(..) => allocs >= 2,
+            _ => false,
+        })
+    };
+    let ops = random_ops(7, 200);
+    assert!(fails(&ops));
+    assert_eq!(
+        shrink(ops, fails),
+        vec![Op::Alloc, Op::Alloc, Op::Link(0, 0)]
+    );
+}
+
+/// Revives its neighbour from its `Drop`.
+enum Revive {}
+
+/// Clones its neighbour into `stash` when dropped, or moves it there if
+/// `take` is set.
+#[derive(Trace)]
+struct Reviver {
+    to: RefCell<Option<Cc<Node<Revive>>>>,
+    #[trace(skip)]
+    stash: Rc<RefCell<Vec<Cc<Node<Revive>>>>>,
+    #[trace(skip)]
+    take: bool,
+}
+
+impl Kind for Revive {
+    type Edges = Reviver;
+}
+
+impl Drop for Reviver {
+    fn drop(&mut self) {
+        if self.take {
+            self.stash.borrow_mut().extend(self.to.borrow_mut().take());
+        } else if let Some(to) = &*self.to.borrow() {
+            self.stash.borrow_mut().push(to.clone());
+        }
+    }
+}
+
+#[test]
+fn test_revive_from_drop() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let stash = Rc::new(RefCell::new(Vec::new()));
+    let new = || {
+        let reviver = Reviver {
+            to: None.into(),
+            stash: stash.clone(),
+            take: false,
+        };
+        Cc::new(Node::<Revive>::with(reviver, &drops), &root)
+    };
+    let obj1 = new();
+    let obj2 = new();
+    *obj1.edges.to.borrow_mut() = Some(obj2.clone());
+    *obj2.edges.to.borrow_mut() = Some(obj1);
+    drop(obj2);
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| root.collect_cycles()))
+        .unwrap_err();
+    let msg = err.downcast_ref::<String>().unwrap();
+    assert!(msg.contains("garbage cycle being collected"), "{}", msg);
+    // Nothing got revived; the cycle is leaked instead.
+    assert!(stash.borrow().is_empty());
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn test_escape_from_drop() {
+    let root = Arc::new(CycleCollector::with_policy(CollectionPolicy::Manual));
+    let drops = Drops::default();
+    let stash = Rc::new(RefCell::new(Vec::new()));
+    let new = || {
+        let reviver = Reviver {
+            to: None.into(),
+            stash: stash.clone(),
+            take: true,
+        };
+        Cc::new(Node::<Revive>::with(reviver, &drops), &root)
+    };
+    let obj1 = new();
+    let obj2 = new();
+    *obj1.edges.to.borrow_mut() = Some(obj2.clone());
+    *obj2.edges.to.borrow_mut() = Some(obj1);
+    let weak = obj2.downgrade();
+    drop(obj2);
+    let stats = root.collect_cycles();
+    assert_eq!((stats.white, stats.freed), (2, 0));
+    assert_eq!(drops.get(), 2);
+    // Both values are gone, but the boxes are kept for the moved out `Cc`s.
+    let escaped = std::mem::take(&mut *stash.borrow_mut());
+    assert_eq!(escaped.len(), 2);
+    assert!(escaped.iter().all(|cc| Cc::strong_count(cc) == 1));
+    assert!(weak.upgrade().is_none());
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| escaped[0].edges.take))
+        .unwrap_err();
+    assert!(err.downcast_ref::<&str>().unwrap().contains("Invalid access"));
+    let fresh = new();
+    assert!(escaped.iter().all(|cc| !Cc::ptr_eq(cc, &fresh)));
+    drop(fresh);
+
+    drop(escaped);
+    assert_eq!(root.collect_cycles().freed, 0);
+    assert!(weak.upgrade().is_none());
+    drop(weak);
+    assert!(stash.borrow().is_empty());
 }
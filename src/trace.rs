@@ -1,18 +1,126 @@
-use crate::CcBoxPtr;
+use std::cell::Cell;
+
+use crate::CcBoxMetaData;
 
 /// A `Tracer` is a callback function that is invoked for each `CcBoxPtr` owned
-/// by an instance of something.
-pub type Tracer<'a> = dyn FnMut(&(dyn CcBoxPtr + 'static)) + 'a;
+/// by an instance of something, with a thin reference to the box's header.
+pub type Tracer<'a> = dyn FnMut(&CcBoxMetaData) + 'a;
 
 /// A trait that informs cycle collector how to find memory that is owned by a
 /// `Trace` instance and managed by the cycle collector.
-pub trait Trace {
+///
+/// `#[derive(Trace)]` and [`empty_trace!`](crate::empty_trace) implement it
+/// without any `unsafe` on your side.
+///
+/// # Safety
+///
+/// The collector takes one off a box's strong count for every time the box
+/// is reported, and frees what gets down to zero. `trace` must therefore
+/// report:
+/// - only `CcBoxPtr`s owned by `self`, whose strong reference goes away when
+///   `self` is dropped, and not ones behind a reference, an `Rc` or an `Arc`
+///   that others may report too;
+/// - each of them at most once per call;
+/// - the same ones on every call, as long as `self` isn't mutated in
+///   between.
+///
+/// Leaving some out is fine: they only can't be collected as part of a cycle.
+pub unsafe trait Trace {
     /// Invoke the `Tracer` on each of the `CcBoxPtr`s owned by this `Trace`
     /// instance.
     ///
     /// Failing to invoke the tracer on every owned `CcBoxPtr` can lead to
     /// leaking cycles.
+    ///
+    /// A `Cc` dropped in here only lets go of its box once the collection
+    /// that called `trace` is over, so one cloned and dropped again every
+    /// time keeps its cycle from ever being collected.
     fn trace(&self, tracer: &mut Tracer);
+
+    /// Whether values of this type never own a `CcBoxPtr`, so that they can't
+    /// be part of a cycle.
+    ///
+    /// Boxes of acyclic types are colored Green when allocated, and the
+    /// collector leaves them out of the roots buffer and of every trace. A
+    /// type that does own one anyway only risks its cycles leaking.
+    ///
+    /// Implementations may depend on the answer for the types they contain,
+    /// as long as that can't lead back to `Self`, which a recursive type
+    /// would.
+    #[inline]
+    fn is_acyclic() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+}
+
+thread_local! {
+    /// Whether the `trace` running on this thread called `untraceable`.
+    static UNTRACEABLE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Tell the collector that the `trace` currently running can't reach some of
+/// the `CcBoxPtr`s the value owns right now, e.g. because they sit behind a
+/// mutably borrowed `RefCell` or a locked `Mutex`.
+///
+/// The collector then treats the box as reachable from outside for the rest
+/// of the collection, so that nothing it owns is collected.
+pub fn untraceable() {
+    UNTRACEABLE.with(|u| u.set(true));
+}
+
+/// Run `f`, which traces one value, and return whether it got through all of
+/// it.
+pub(crate) fn traced_fully(f: impl FnOnce()) -> bool {
+    let outer = UNTRACEABLE.with(|u| u.replace(false));
+    f();
+    !UNTRACEABLE.with(|u| u.replace(outer))
+}
+
+/// Implement [`Trace`] for types that never own a `Cc`, reporting nothing and
+/// marking them acyclic.
+///
+/// ```
+/// struct Celsius(f64);
+/// struct Kelvin(f64);
+/// struct Id<T>(u64, std::marker::PhantomData<T>);
+///
+/// cc_bacon::empty_trace!(Celsius, Kelvin);
+/// cc_bacon::empty_trace!(impl<T> Id<T>);
+/// ```
+///
+/// This is always safe: at worst, a type that does own a `Cc` leaks the
+/// cycles it is part of.
+#[macro_export]
+macro_rules! empty_trace {
+    ($(impl<$($param:ident),+> $ty:ty),+ $(,)?) => {
+        $(
+            unsafe impl<$($param),+> $crate::Trace for $ty {
+                #[inline]
+                fn trace(&self, _tracer: &mut $crate::Tracer) {}
+
+                #[inline]
+                fn is_acyclic() -> bool {
+                    true
+                }
+            }
+        )+
+    };
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            unsafe impl $crate::Trace for $ty {
+                #[inline]
+                fn trace(&self, _tracer: &mut $crate::Tracer) {}
+
+                #[inline]
+                fn is_acyclic() -> bool {
+                    true
+                }
+            }
+        )+
+    };
 }
 
 mod impls {
@@ -20,18 +128,10 @@ mod impls {
 
     mod primitives {
         use super::*;
+        use std::num;
+        use std::sync::atomic;
 
-        macro_rules! impl_prim {
-            ($($t:ty,)*) => {
-                $(
-                    impl Trace for $t {
-                        fn trace(&self, _tracer: &mut Tracer) {}
-                    }
-                )*
-            }
-        }
-
-        impl_prim! {
+        empty_trace! {
             bool,
             char,
             f32,
@@ -48,23 +148,62 @@ mod impls {
             u64,
             u128,
             usize,
-            str,
             (),
+            num::NonZeroI8,
+            num::NonZeroI16,
+            num::NonZeroI32,
+            num::NonZeroI64,
+            num::NonZeroI128,
+            num::NonZeroIsize,
+            num::NonZeroU8,
+            num::NonZeroU16,
+            num::NonZeroU32,
+            num::NonZeroU64,
+            num::NonZeroU128,
+            num::NonZeroUsize,
+            atomic::AtomicBool,
+            atomic::AtomicI8,
+            atomic::AtomicI16,
+            atomic::AtomicI32,
+            atomic::AtomicI64,
+            atomic::AtomicIsize,
+            atomic::AtomicU8,
+            atomic::AtomicU16,
+            atomic::AtomicU32,
+            atomic::AtomicU64,
+            atomic::AtomicUsize,
+        }
+
+        unsafe impl<T> Trace for atomic::AtomicPtr<T> {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T: Trace + ?Sized> Trace for &'_ T {
-            fn trace(&self, tracer: &mut Tracer) {
-                (**self).trace(tracer)
+        unsafe impl Trace for str {
+            fn trace(&self, _tracer: &mut Tracer) {}
+        }
+
+        // Borrowed, so whatever they point to is owned by someone else.
+        unsafe impl<T: ?Sized> Trace for &'_ T {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
             }
         }
 
-        impl<T: Trace + ?Sized> Trace for &'_ mut T {
-            fn trace(&self, tracer: &mut Tracer) {
-                (**self).trace(tracer)
+        unsafe impl<T: ?Sized> Trace for &'_ mut T {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
             }
         }
 
-        impl<T: Trace> Trace for [T] {
+        unsafe impl<T: Trace> Trace for [T] {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
@@ -72,18 +211,77 @@ mod impls {
             }
         }
 
-        // requires 1.51
-        // mod arrays {
-        //     use super::*;
+        mod arrays {
+            use super::*;
+
+            unsafe impl<T: Trace, const N: usize> Trace for [T; N] {
+                fn trace(&self, tracer: &mut Tracer) {
+                    for t in self {
+                        t.trace(tracer);
+                    }
+                }
+
+                fn is_acyclic() -> bool {
+                    T::is_acyclic()
+                }
+            }
+        }
+
+        mod functions {
+            use super::*;
 
-        //     impl<T: Trace, const N: usize> Trace for [T; N] {
-        //         fn trace(&self, tracer: &mut Tracer) {
-        //             for t in self {
-        //                 t.trace(tracer);
-        //             }
-        //         }
-        //     }
-        // }
+            // Only plain signatures; ones with higher-ranked lifetimes like
+            // `fn(&T)` don't match these.
+            macro_rules! fn_impls {
+                ($($A:ident)*) => {
+                    unsafe impl<R $(, $A)*> Trace for fn($($A),*) -> R {
+                        fn trace(&self, _tracer: &mut Tracer) {}
+
+                        fn is_acyclic() -> bool {
+                            true
+                        }
+                    }
+
+                    unsafe impl<R $(, $A)*> Trace for unsafe fn($($A),*) -> R {
+                        fn trace(&self, _tracer: &mut Tracer) {}
+
+                        fn is_acyclic() -> bool {
+                            true
+                        }
+                    }
+
+                    unsafe impl<R $(, $A)*> Trace for extern "C" fn($($A),*) -> R {
+                        fn trace(&self, _tracer: &mut Tracer) {}
+
+                        fn is_acyclic() -> bool {
+                            true
+                        }
+                    }
+
+                    unsafe impl<R $(, $A)*> Trace for unsafe extern "C" fn($($A),*) -> R {
+                        fn trace(&self, _tracer: &mut Tracer) {}
+
+                        fn is_acyclic() -> bool {
+                            true
+                        }
+                    }
+                }
+            }
+
+            fn_impls!();
+            fn_impls!(A);
+            fn_impls!(A B);
+            fn_impls!(A B C);
+            fn_impls!(A B C D);
+            fn_impls!(A B C D E);
+            fn_impls!(A B C D E F);
+            fn_impls!(A B C D E F G);
+            fn_impls!(A B C D E F G H);
+            fn_impls!(A B C D E F G H I);
+            fn_impls!(A B C D E F G H I J);
+            fn_impls!(A B C D E F G H I J K);
+            fn_impls!(A B C D E F G H I J K L);
+        }
 
         mod tuples {
             use super::*;
@@ -97,7 +295,9 @@ mod impls {
                     }
                 )+) => {
                     $(
-                        impl<$($T: Trace),+> Trace for ($($T,)+) where last_type!($($T,)+): ?Sized {
+                        // `is_acyclic` would need the last element to be
+                        // sized, so tuples keep the default.
+                        unsafe impl<$($T: Trace),+> Trace for ($($T,)+) where last_type!($($T,)+): ?Sized {
                             fn trace(&self, t: &mut Tracer) {
                                 $(
                                     self.$idx.trace(t);
@@ -216,6 +416,72 @@ mod impls {
                     (10) -> K
                     (11) -> L
                 }
+                Tuple13 {
+                    (0) -> A
+                    (1) -> B
+                    (2) -> C
+                    (3) -> D
+                    (4) -> E
+                    (5) -> F
+                    (6) -> G
+                    (7) -> H
+                    (8) -> I
+                    (9) -> J
+                    (10) -> K
+                    (11) -> L
+                    (12) -> M
+                }
+                Tuple14 {
+                    (0) -> A
+                    (1) -> B
+                    (2) -> C
+                    (3) -> D
+                    (4) -> E
+                    (5) -> F
+                    (6) -> G
+                    (7) -> H
+                    (8) -> I
+                    (9) -> J
+                    (10) -> K
+                    (11) -> L
+                    (12) -> M
+                    (13) -> N
+                }
+                Tuple15 {
+                    (0) -> A
+                    (1) -> B
+                    (2) -> C
+                    (3) -> D
+                    (4) -> E
+                    (5) -> F
+                    (6) -> G
+                    (7) -> H
+                    (8) -> I
+                    (9) -> J
+                    (10) -> K
+                    (11) -> L
+                    (12) -> M
+                    (13) -> N
+                    (14) -> O
+                }
+                Tuple16 {
+                    (0) -> A
+                    (1) -> B
+                    (2) -> C
+                    (3) -> D
+                    (4) -> E
+                    (5) -> F
+                    (6) -> G
+                    (7) -> H
+                    (8) -> I
+                    (9) -> J
+                    (10) -> K
+                    (11) -> L
+                    (12) -> M
+                    (13) -> N
+                    (14) -> O
+                    (15) -> P
+                }
             }
         }
     }
@@ -223,28 +489,93 @@ mod impls {
     mod boxed {
         use super::*;
 
-        impl<T: Trace + ?Sized> Trace for Box<T> {
+        unsafe impl<T: Trace> Trace for Box<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                (**self).trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        // `is_acyclic` can't be forwarded for unsized values in general, so
+        // these are spelled out.
+        unsafe impl<T: Trace> Trace for Box<[T]> {
+            fn trace(&self, tracer: &mut Tracer) {
+                (**self).trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl Trace for Box<str> {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for Box<dyn Trace> {
             fn trace(&self, tracer: &mut Tracer) {
                 (**self).trace(tracer);
             }
         }
     }
 
+    mod borrow {
+        use super::*;
+        use std::borrow::{Cow, ToOwned};
+
+        unsafe impl<B: ToOwned + Trace + ?Sized> Trace for Cow<'_, B>
+        where
+            B::Owned: Trace,
+        {
+            fn trace(&self, tracer: &mut Tracer) {
+                // A borrowed value belongs to someone else.
+                if let Cow::Owned(owned) = self {
+                    owned.trace(tracer);
+                }
+            }
+
+            fn is_acyclic() -> bool {
+                B::Owned::is_acyclic()
+            }
+        }
+    }
+
     mod cell {
         use super::*;
         use std::cell;
 
-        impl<T: Copy + Trace + ?Sized> Trace for cell::Cell<T> {
+        unsafe impl<T: Default + Trace> Trace for cell::Cell<T> {
             fn trace(&self, tracer: &mut Tracer) {
-                self.get().trace(tracer);
+                // There's no borrowing the contents, so trace them out of
+                // the cell and put them back.
+                let value = self.take();
+                value.trace(tracer);
+                self.set(value);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
             }
         }
 
-        impl<T: Trace + ?Sized> Trace for cell::RefCell<T> {
+        unsafe impl<T: Trace> Trace for cell::RefCell<T> {
             fn trace(&self, tracer: &mut Tracer) {
-                // We'll panic if we can't borrow. I'm not
-                // sure if we have a better option.
-                self.borrow().trace(tracer);
+                match self.try_borrow() {
+                    Ok(value) => value.trace(tracer),
+                    // Borrowed by the code that made the collector run.
+                    Err(_) => untraceable(),
+                }
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
             }
         }
     }
@@ -253,72 +584,282 @@ mod impls {
         use super::*;
         use std::collections;
 
-        impl<K: Trace, V: Trace> Trace for collections::BTreeMap<K, V> {
+        unsafe impl<K: Trace, V: Trace> Trace for collections::BTreeMap<K, V> {
             fn trace(&self, tracer: &mut Tracer) {
                 for (k, v) in self {
                     k.trace(tracer);
                     v.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                K::is_acyclic() && V::is_acyclic()
+            }
         }
 
-        impl<T: Trace> Trace for collections::BTreeSet<T> {
+        unsafe impl<T: Trace> Trace for collections::BinaryHeap<T> {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
 
-        impl<K: Trace, V: Trace, S> Trace for collections::HashMap<K, V, S> {
+        unsafe impl<T: Trace> Trace for collections::BTreeSet<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                for t in self {
+                    t.trace(tracer);
+                }
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl<K: Trace, V: Trace, S> Trace for collections::HashMap<K, V, S> {
             fn trace(&self, tracer: &mut Tracer) {
                 for (k, v) in self {
                     k.trace(tracer);
                     v.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                K::is_acyclic() && V::is_acyclic()
+            }
         }
 
-        impl<T: Trace, S> Trace for collections::HashSet<T, S> {
+        unsafe impl<T: Trace, S> Trace for collections::HashSet<T, S> {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
 
-        impl<T: Trace> Trace for collections::LinkedList<T> {
+        unsafe impl<T: Trace> Trace for collections::LinkedList<T> {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
 
-        impl<T: Trace> Trace for collections::VecDeque<T> {
+        unsafe impl<T: Trace> Trace for collections::VecDeque<T> {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+    }
+
+    mod cmp {
+        use super::*;
+        use std::cmp;
+
+        unsafe impl<T: Trace> Trace for cmp::Reverse<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.0.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+    }
+
+    mod marker {
+        use super::*;
+        use std::marker;
+
+        unsafe impl<T: ?Sized> Trace for marker::PhantomData<T> {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for marker::PhantomPinned {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+    }
+
+    mod mem {
+        use super::*;
+        use std::mem;
+
+        // Whoever drops the value drops its `Cc`s, so they are still owned.
+        unsafe impl<T: Trace> Trace for mem::ManuallyDrop<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                (**self).trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+    }
+
+    mod num {
+        use super::*;
+        use std::num;
+
+        unsafe impl<T: Trace> Trace for num::Wrapping<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.0.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+    }
+
+    mod ops {
+        use super::*;
+        use std::ops;
+
+        unsafe impl<T: Trace> Trace for ops::Range<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.start.trace(tracer);
+                self.end.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl<T: Trace> Trace for ops::RangeFrom<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.start.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl Trace for ops::RangeFull {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl<T: Trace> Trace for ops::RangeInclusive<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.start().trace(tracer);
+                self.end().trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl<T: Trace> Trace for ops::RangeTo<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.end.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl<T: Trace> Trace for ops::RangeToInclusive<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                self.end.trace(tracer);
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+
+        unsafe impl<T: Trace> Trace for ops::Bound<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                match self {
+                    ops::Bound::Included(t) | ops::Bound::Excluded(t) => t.trace(tracer),
+                    ops::Bound::Unbounded => {}
+                }
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
+        }
+    }
+
+    mod pin {
+        use super::*;
+        use std::pin::Pin;
+
+        // Only pointers that own what they point to; a pinned reference or
+        // `Rc` traces nothing like the unpinned ones.
+        unsafe impl<T: Trace + ?Sized> Trace for Pin<Box<T>> {
+            fn trace(&self, tracer: &mut Tracer) {
+                (**self).trace(tracer);
+            }
+        }
+
+        unsafe impl<T: ?Sized> Trace for Pin<&'_ T> {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
     mod vec {
         use super::*;
-        impl<T: Trace> Trace for Vec<T> {
+        unsafe impl<T: Trace> Trace for Vec<T> {
             fn trace(&self, tracer: &mut Tracer) {
                 for t in self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
     }
 
     mod string {
         use super::*;
-        impl Trace for String {
+        unsafe impl Trace for String {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
@@ -326,24 +867,36 @@ mod impls {
         use super::*;
         use std::ffi;
 
-        impl Trace for ffi::CStr {
+        unsafe impl Trace for ffi::CStr {
             fn trace(&self, _tracer: &mut Tracer) {}
         }
 
-        impl Trace for ffi::CString {
+        unsafe impl Trace for ffi::CString {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for ffi::NulError {
+        unsafe impl Trace for ffi::NulError {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for ffi::OsStr {
+        unsafe impl Trace for ffi::OsStr {
             fn trace(&self, _tracer: &mut Tracer) {}
         }
 
-        impl Trace for ffi::OsString {
+        unsafe impl Trace for ffi::OsString {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
@@ -351,64 +904,124 @@ mod impls {
         use super::*;
         use std::io;
 
-        impl<T> Trace for io::BufReader<T> {
+        unsafe impl<T> Trace for io::BufReader<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T: io::Write> Trace for io::BufWriter<T> {
+        unsafe impl<T: io::Write> Trace for io::BufWriter<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for io::Cursor<T> {
+        unsafe impl<T> Trace for io::Cursor<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Empty {
+        unsafe impl Trace for io::Empty {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Error {
+        unsafe impl Trace for io::Error {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for io::IntoInnerError<T> {
+        unsafe impl<T> Trace for io::IntoInnerError<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T: io::Write> Trace for io::LineWriter<T> {
+        unsafe impl<T: io::Write> Trace for io::LineWriter<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for io::Lines<T> {
+        unsafe impl<T> Trace for io::Lines<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Repeat {
+        unsafe impl Trace for io::Repeat {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Sink {
+        unsafe impl Trace for io::Sink {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for io::Split<T> {
+        unsafe impl<T> Trace for io::Split<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Stderr {
+        unsafe impl Trace for io::Stderr {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Stdin {
+        unsafe impl Trace for io::Stdin {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for io::Stdout {
+        unsafe impl Trace for io::Stdout {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for io::Take<T> {
+        unsafe impl<T> Trace for io::Take<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
@@ -416,48 +1029,100 @@ mod impls {
         use super::*;
         use std::net;
 
-        impl Trace for net::AddrParseError {
+        unsafe impl Trace for net::AddrParseError {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::Ipv4Addr {
+        unsafe impl Trace for net::IpAddr {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for net::Ipv4Addr {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::Ipv6Addr {
+        unsafe impl Trace for net::Ipv6Addr {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::SocketAddrV4 {
+        unsafe impl Trace for net::SocketAddr {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::SocketAddrV6 {
+        unsafe impl Trace for net::SocketAddrV4 {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::TcpListener {
+        unsafe impl Trace for net::SocketAddrV6 {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::TcpStream {
+        unsafe impl Trace for net::TcpListener {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for net::UdpSocket {
+        unsafe impl Trace for net::TcpStream {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for net::UdpSocket {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
     mod option {
         use super::*;
 
-        impl<T: Trace> Trace for Option<T> {
+        unsafe impl<T: Trace> Trace for Option<T> {
             fn trace(&self, tracer: &mut Tracer) {
                 if let Some(ref t) = *self {
                     t.trace(tracer);
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
     }
 
@@ -465,12 +1130,16 @@ mod impls {
         use super::*;
         use std::path;
 
-        impl Trace for path::Path {
+        unsafe impl Trace for path::Path {
             fn trace(&self, _tracer: &mut Tracer) {}
         }
 
-        impl Trace for path::PathBuf {
+        unsafe impl Trace for path::PathBuf {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
@@ -478,62 +1147,108 @@ mod impls {
         use super::*;
         use std::process;
 
-        impl Trace for process::Child {
+        unsafe impl Trace for process::Child {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::ChildStderr {
+        unsafe impl Trace for process::ChildStderr {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::ChildStdin {
+        unsafe impl Trace for process::ChildStdin {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::ChildStdout {
+        unsafe impl Trace for process::ChildStdout {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::Command {
+        unsafe impl Trace for process::Command {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::ExitStatus {
+        unsafe impl Trace for process::ExitStatus {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::Output {
+        unsafe impl Trace for process::Output {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for process::Stdio {
+        unsafe impl Trace for process::Stdio {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
+    // Shared, so every owner would report the same `Cc`s. Cycles through
+    // them leak.
     mod rc {
         use super::*;
         use std::rc;
 
-        impl<T> Trace for rc::Rc<T> {
+        unsafe impl<T> Trace for rc::Rc<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for rc::Weak<T> {
+        unsafe impl<T> Trace for rc::Weak<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 
     mod result {
         use super::*;
 
-        impl<T: Trace, U: Trace> Trace for Result<T, U> {
+        unsafe impl<T: Trace, U: Trace> Trace for Result<T, U> {
             fn trace(&self, tracer: &mut Tracer) {
                 match *self {
                     Ok(ref t) => t.trace(tracer),
                     Err(ref u) => u.trace(tracer),
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic() && U::is_acyclic()
+            }
         }
     }
 
@@ -541,36 +1256,74 @@ mod impls {
         use super::*;
         use std::sync;
 
-        impl<T> Trace for sync::Arc<T> {
+        // Like `Rc`, shared with other owners.
+        unsafe impl<T> Trace for sync::Arc<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for sync::Barrier {
+        unsafe impl Trace for sync::Barrier {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for sync::Condvar {
+        unsafe impl Trace for sync::Condvar {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for sync::Mutex<T> {
-            fn trace(&self, _tracer: &mut Tracer) {}
+        unsafe impl<T: Trace> Trace for sync::Mutex<T> {
+            fn trace(&self, tracer: &mut Tracer) {
+                match self.try_lock() {
+                    Ok(value) => value.trace(tracer),
+                    Err(sync::TryLockError::Poisoned(value)) => value.into_inner().trace(tracer),
+                    // Blocking could deadlock on a lock held by this thread.
+                    Err(sync::TryLockError::WouldBlock) => untraceable(),
+                }
+            }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
 
-        impl Trace for sync::Once {
+        unsafe impl Trace for sync::Once {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for sync::PoisonError<T> {
+        unsafe impl<T> Trace for sync::PoisonError<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T: Trace> Trace for sync::RwLock<T> {
+        unsafe impl<T: Trace> Trace for sync::RwLock<T> {
             fn trace(&self, tracer: &mut Tracer) {
-                if let Ok(v) = self.write() {
-                    v.trace(tracer);
+                match self.try_read() {
+                    Ok(value) => value.trace(tracer),
+                    Err(sync::TryLockError::Poisoned(value)) => value.into_inner().trace(tracer),
+                    Err(sync::TryLockError::WouldBlock) => untraceable(),
                 }
             }
+
+            fn is_acyclic() -> bool {
+                T::is_acyclic()
+            }
         }
     }
 
@@ -578,20 +1331,65 @@ mod impls {
         use super::*;
         use std::thread;
 
-        impl Trace for thread::Builder {
+        unsafe impl Trace for thread::Builder {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for thread::JoinHandle<T> {
+        unsafe impl<T> Trace for thread::JoinHandle<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl<T> Trace for thread::LocalKey<T> {
+        unsafe impl<T> Trace for thread::LocalKey<T> {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
 
-        impl Trace for thread::Thread {
+        unsafe impl Trace for thread::Thread {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+    }
+
+    mod time {
+        use super::*;
+        use std::time;
+
+        unsafe impl Trace for time::Duration {
             fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for time::Instant {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
+        }
+
+        unsafe impl Trace for time::SystemTime {
+            fn trace(&self, _tracer: &mut Tracer) {}
+
+            fn is_acyclic() -> bool {
+                true
+            }
         }
     }
 }
@@ -0,0 +1,98 @@
+//! Check the invariants the collector relies on between collections, for
+//! tests and fuzzing.
+use std::collections::HashSet;
+
+use crate::trace::traced_fully;
+use crate::{CcBoxPtr, CcPtr, Color, CycleCollector, Trace};
+
+/// A broken heap invariant found by [`CycleCollector::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub ptr: CcPtr,
+    /// Type of the value in the box.
+    pub type_name: &'static str,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The buffered flag is set, but the box isn't in the roots buffer.
+    BufferedNotInRoots,
+    /// The box is in the roots buffer without its buffered flag.
+    InRootsNotBuffered,
+    /// Gray or White, which only a collection in progress leaves behind.
+    Color(Color),
+    /// Released, yet still reported by the `Trace` impl of a live box.
+    ReleasedButReachable { from: CcPtr },
+    /// Alive without the weak reference all strong ones share.
+    NoWeakWhileStrong,
+}
+
+impl CycleCollector {
+    /// Walk the roots buffer and everything reachable from it, and return
+    /// every broken invariant found there.
+    ///
+    /// Returns nothing in the middle of a collection or release, whose
+    /// counts and colors are in flux.
+    pub fn verify(&self) -> Vec<Violation> {
+        if self.collecting.get() || self.releasing.get() {
+            return Vec::new();
+        }
+        let _guard = self.begin_collection();
+        let violations = self.tracing(|| self.verify_roots());
+        self.end_collection();
+        violations
+    }
+
+    fn verify_roots(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let roots = self.roots.borrow().clone();
+        let in_roots: HashSet<CcPtr> = roots.iter().copied().collect();
+        let mut seen = in_roots.clone();
+        let mut stack = roots;
+        while let Some(s) = stack.pop() {
+            let obj = unsafe { s.as_ref() };
+            let mut violation = |kind| {
+                violations.push(Violation {
+                    ptr: s,
+                    type_name: obj.type_name(),
+                    kind,
+                })
+            };
+            match (obj.buffered(), in_roots.contains(&s)) {
+                (true, false) => violation(ViolationKind::BufferedNotInRoots),
+                (false, true) => violation(ViolationKind::InRootsNotBuffered),
+                _ => {}
+            }
+            if let color @ (Color::Gray | Color::White) = obj.color() {
+                violation(ViolationKind::Color(color));
+            }
+            // Released boxes waiting in the roots buffer have nothing left
+            // to trace.
+            if obj.strong() == 0 {
+                continue;
+            }
+            if obj.weak() == 0 {
+                violation(ViolationKind::NoWeakWhileStrong);
+            }
+            if obj.color() == Color::Green {
+                continue;
+            }
+            let mut children = Vec::new();
+            traced_fully(|| obj.trace(&mut |ch| children.push(ch.get_ptr())));
+            for ch in children {
+                let child = unsafe { ch.as_ref() };
+                if child.strong() == 0 && child.color() == Color::Black {
+                    violations.push(Violation {
+                        ptr: ch,
+                        type_name: child.type_name(),
+                        kind: ViolationKind::ReleasedButReachable { from: s },
+                    });
+                } else if seen.insert(ch) {
+                    stack.push(ch);
+                }
+            }
+        }
+        violations
+    }
+}